@@ -0,0 +1,145 @@
+use crate::components::{Clip, EntityAnimation, LoopMode, MovementAnimation, Playback, Sprite};
+use crate::textures::{TextureHandle, TextureRegistry};
+use std::{collections::HashMap, fs};
+use regex::Regex;
+use sdl2::rect::Rect;
+
+// Sprite sheet file format:
+//
+// sheet reaper reaper 32 36        Starts a sheet: its name, the texture from the manifest, and the width
+//                                  and height of every frame.
+// clip walk_down 0 0 3             A named clip of the last sheet: column and row of its first frame,
+//                                  counted in frames, and how many frames it has, running to the right.
+// clip crate 0 4 4 down            Frames that run down a column instead.
+// clip open 0 0 4 once 80 80 200   How it plays: loop, the default, once or pingpong. Then milliseconds to
+//                                  show each frame for, or one number for all of them. Without any,
+//                                  frames last DEFAULT_FRAME_MS.
+// aseprite swing assets/swing.json A whole sheet exported from Aseprite, see src/aseprite.rs. An optional
+//                                  texture name can follow the file.
+//
+// Lines starting with // are comments. Characters that walk need the clips walk_up, walk_down,
+// walk_left and walk_right.
+
+pub const DEFAULT_FRAME_MS: u32 = 100;
+
+#[derive(Debug, Clone)]
+pub struct SpriteSheet {
+    pub clips: HashMap<String, Clip>,
+    // Named regions of the frames, each with the frame it starts applying from. Only Aseprite exports
+    // have them.
+    pub slices: HashMap<String, Vec<(usize, Rect)>>,
+    // Which exported frame each frame of a clip is, for finding the slice keys that apply to it.
+    pub sources: HashMap<String, Vec<usize>>,
+}
+
+#[derive(Debug, Default)]
+pub struct SpriteSheets {
+    sheets: HashMap<String, SpriteSheet>,
+}
+
+impl SpriteSheets {
+    pub fn load(path: &str, textures: &TextureRegistry) -> Result<Self, String> {
+        let source = fs::read_to_string(path).map_err(|e| format!("Could not read sprite sheet file {}: {}", path, e))?;
+        Self::parse(&source, textures).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    fn parse(source: &str, textures: &TextureRegistry) -> Result<Self, String> {
+        let sheet_pattern = Regex::new(r"^sheet (\w+) (\w+) (\d+) (\d+)$").unwrap();
+        let clip_pattern = Regex::new(r"^clip (\w+) (\d+) (\d+) (\d+)( down)?(?: (loop|once|pingpong))?((?: \d+)*)$").unwrap();
+        let aseprite_pattern = Regex::new(r"^aseprite (\w+) (\S+)(?: (\w+))?$").unwrap();
+
+        let mut sheets = SpriteSheets::default();
+        // Name, texture and frame size of the sheet clips are added to.
+        let mut current: Option<(String, TextureHandle, u32, u32)> = None;
+        for (line_no, line) in source.lines().enumerate().map(|(i, l)| (i + 1, l.trim())) {
+            if line.is_empty() || line.starts_with("//") {
+                continue;
+            }
+            let number = |text: &str| text.parse::<u32>().map_err(|e| format!("line {}: {}", line_no, e));
+            if let Some(cap) = sheet_pattern.captures(line) {
+                let texture = textures.get(&cap[2])
+                    .ok_or_else(|| format!("line {}: unknown texture \"{}\"", line_no, &cap[2]))?;
+                if sheets.sheets.contains_key(&cap[1]) {
+                    return Err(format!("line {}: there already is a sheet \"{}\"", line_no, &cap[1]));
+                }
+                sheets.sheets.insert(cap[1].to_string(), SpriteSheet { clips: HashMap::new(), slices: HashMap::new(), sources: HashMap::new() });
+                current = Some((cap[1].to_string(), texture, number(&cap[3])?, number(&cap[4])?));
+            } else if let Some(cap) = aseprite_pattern.captures(line) {
+                if sheets.sheets.contains_key(&cap[1]) {
+                    return Err(format!("line {}: there already is a sheet \"{}\"", line_no, &cap[1]));
+                }
+                let sheet = crate::aseprite::load(&cap[2], textures, cap.get(3).map(|m| m.as_str()))
+                    .map_err(|e| format!("line {}: {}", line_no, e))?;
+                sheets.sheets.insert(cap[1].to_string(), sheet);
+                // Its clips all come from the export.
+                current = None;
+            } else if let Some(cap) = clip_pattern.captures(line) {
+                let (sheet, texture, width, height) = current.as_ref()
+                    .ok_or_else(|| format!("line {}: a clip needs a sheet line before it", line_no))?;
+                let (column, row, count) = (number(&cap[2])?, number(&cap[3])?, number(&cap[4])?);
+                if count == 0 {
+                    return Err(format!("line {}: clip \"{}\" has no frames", line_no, &cap[1]));
+                }
+                let down = cap.get(5).is_some();
+                let frames = (0..count)
+                    .map(|i| {
+                        let (column, row) = if down { (column, row + i) } else { (column + i, row) };
+                        Sprite {
+                            spritesheet: *texture,
+                            region: Rect::new((column * width) as i32, (row * height) as i32, *width, *height),
+                        }
+                    })
+                    .collect();
+
+                let mode = cap.get(6).and_then(|m| LoopMode::from_token(m.as_str())).unwrap_or(LoopMode::Loop);
+                let durations = cap[7].split_whitespace().map(number).collect::<Result<Vec<u32>, String>>()?;
+                let durations = match durations.len() {
+                    0 => vec![DEFAULT_FRAME_MS; count as usize],
+                    1 => vec![durations[0]; count as usize],
+                    n if n == count as usize => durations,
+                    n => return Err(format!("line {}: clip \"{}\" has {} frames but {} durations", line_no, &cap[1], count, n)),
+                };
+                sheets.sheets.get_mut(sheet).unwrap().clips.insert(cap[1].to_string(), Clip { frames, durations, mode });
+            } else {
+                return Err(format!("line {}: could not parse \"{}\"", line_no, line));
+            }
+        }
+        Ok(sheets)
+    }
+
+    pub fn clip(&self, sheet: &str, clip: &str) -> Result<&Clip, String> {
+        self.sheets.get(sheet)
+            .ok_or_else(|| format!("There is no sprite sheet \"{}\"", sheet))?
+            .clips.get(clip)
+            .ok_or_else(|| format!("Sprite sheet \"{}\" has no clip \"{}\"", sheet, clip))
+    }
+
+    // The first frame of a clip, for things that don't animate.
+    pub fn sprite(&self, sheet: &str, clip: &str) -> Result<Sprite, String> {
+        Ok(self.clip(sheet, clip)?.frames[0])
+    }
+
+    // Where a slice is within a frame of a clip: its last key at or before that frame. None if the
+    // slice has no key by then.
+    pub fn slice(&self, sheet: &str, name: &str, clip: &str, frame: usize) -> Option<Rect> {
+        let sheet = self.sheets.get(sheet)?;
+        let sources = sheet.sources.get(clip)?;
+        let source = sources[frame % sources.len()];
+        sheet.slices.get(name)?.iter().rev().find(|&&(from, _)| from <= source).map(|&(_, rect)| rect)
+    }
+
+    pub fn entity_animation(&self, sheet: &str, clip: &str) -> Result<EntityAnimation, String> {
+        Ok(EntityAnimation::new(self.clip(sheet, clip)?.clone()))
+    }
+
+    pub fn movement_animation(&self, sheet: &str) -> Result<MovementAnimation, String> {
+        let clip = |name: &str| self.clip(sheet, name).cloned();
+        Ok(MovementAnimation {
+            up: clip("walk_up")?,
+            right: clip("walk_right")?,
+            down: clip("walk_down")?,
+            left: clip("walk_left")?,
+            playback: Playback::default(),
+        })
+    }
+}
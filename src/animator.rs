@@ -1,4 +1,4 @@
-use specs::{WriteStorage, System, ReadStorage, join::Join};
+use specs::{WriteStorage, System, ReadStorage, ReadExpect, WriteExpect, join::Join, Entities};
 use crate::components::*;
 
 pub struct Animator;
@@ -11,6 +11,9 @@ impl<'a> System<'a> for Animator {
         ReadStorage<'a, Velocity>,
         WriteStorage<'a, EntityAnimation>,
         ReadStorage<'a, Interactable>,
+        ReadExpect<'a, DeltaTime>,
+        WriteExpect<'a, AnimationEvents>,
+        Entities<'a>,
     );
 
     fn run(&mut self, (
@@ -18,39 +21,60 @@ impl<'a> System<'a> for Animator {
         mut sprite,
         velocity,
         mut entanimation,
-        interactable
+        interactable,
+        delta,
+        mut events,
+        entities,
     ): Self::SystemData) {
-        use crate::components::Direction::*;
-        
+        events.finished.clear();
+        let ms = delta.0.as_secs_f32() * 1000.0;
+
         for (anim, sprite, vel) in (&mut moveanimation, &mut sprite, &velocity).join() {
             // In here, we animate movement.
             if vel.direction.is_empty() {
                 continue;
             }
-            
-            let frames = match vel.direction[0] {
-                Left => &anim.left_frames,
-                Right => &anim.right_frames,
-                Up => &anim.up_frames,
-                Down => &anim.down_frames,
+
+            let MovementAnimation { up, right, down, left, playback } = anim;
+            let clip = match vel.direction[0] {
+                Direction::Left => left,
+                Direction::Right => right,
+                Direction::Up => up,
+                Direction::Down => down,
             };
+            playback.play();
+            playback.advance(clip, ms);
+            *sprite = clip.frame(playback.frame);
+        }
+
+        for (entity, anim, sprite) in (&entities, &mut entanimation, &mut sprite).join() {
+            // In here, we animate interactables, which start once used, and anything already playing.
+            if interactable.get(entity).is_some_and(|obj| obj.interactions > 0) {
+                anim.playback.play();
+            }
+            if !anim.playback.playing {
+                continue;
+            }
+            if anim.playback.advance(&anim.clip, ms) {
+                events.finished.push(entity);
+            }
+            *sprite = anim.sprite();
+        }
 
-            anim.current_frame = (anim.current_frame + 1) % frames.len();
-            *sprite = frames[anim.current_frame];
+        for &entity in &events.finished {
+            // The animation has played out, so it's gone.
+            if let Some(InteractableType::DestroyedOnUse) = interactable.get(entity).map(|obj| &obj.interaction_type) {
+                entities.delete(entity).ok();
+            }
         }
 
-        for (anim, sprite, obj) in (&mut entanimation, &mut sprite, &interactable).join() {
-            // In here, we animate interactables.
-            if obj.interactions > 0 {
-                let frames = &anim.frames;
-                if anim.current_frame >= frames.len()-1 {
-                    continue;
-                } else {
-                    anim.current_frame += 1;
-                    *sprite = frames[anim.current_frame];
+        for (entity, obj, _) in (&entities, &interactable, !&entanimation).join() {
+            // Without an animation there is nothing to wait for.
+            if let InteractableType::DestroyedOnUse = obj.interaction_type {
+                if obj.interactions > 0 {
+                    entities.delete(entity).ok();
                 }
             }
-            
         }
     }
 }
@@ -0,0 +1,171 @@
+use crate::animations::SpriteSheet;
+use crate::components::{Clip, LoopMode, Sprite};
+use crate::textures::TextureRegistry;
+use std::{collections::HashMap, fs, path::Path};
+use serde::Deserialize;
+use serde_json::Value;
+use sdl2::rect::Rect;
+
+// Loads sprite sheets exported from Aseprite (https://www.aseprite.org) as JSON, with the frames
+// exported as an array rather than a hash, since only the array keeps them in order.
+//
+// Every tag becomes a clip with the tag's name, played in the tag's direction. It loops, unless the tag
+// is set to repeat once. Without tags the whole export is one looping clip called `default`. Frame
+// durations come from the export. Slices are kept by name, relative to the top left of the frame, with
+// the frame each key starts on. Trimmed frames aren't supported, since sprites are drawn from the middle
+// of the whole frame.
+//
+// The image is looked up in the texture manifest by file name without the extension, unless a texture
+// name is given.
+
+pub const UNTAGGED_CLIP: &str = "default";
+
+#[derive(Deserialize)]
+struct AsepriteFile {
+    frames: Value,
+    meta: Meta,
+}
+
+#[derive(Deserialize)]
+struct Frame {
+    frame: Bounds,
+    duration: u32,
+    #[serde(default)]
+    trimmed: bool,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+struct Bounds {
+    x: i32,
+    y: i32,
+    w: u32,
+    h: u32,
+}
+
+impl Bounds {
+    fn rect(&self) -> Rect {
+        Rect::new(self.x, self.y, self.w, self.h)
+    }
+}
+
+#[derive(Deserialize)]
+struct Meta {
+    image: String,
+    #[serde(rename = "frameTags", default)]
+    frame_tags: Vec<Tag>,
+    #[serde(default)]
+    slices: Vec<Slice>,
+}
+
+#[derive(Deserialize)]
+struct Tag {
+    name: String,
+    from: usize,
+    to: usize,
+    #[serde(default)]
+    direction: String,
+    // How many times it plays, as a number or a string of one. Without it, forever.
+    #[serde(default)]
+    repeat: Option<Value>,
+}
+
+#[derive(Deserialize)]
+struct Slice {
+    name: String,
+    keys: Vec<SliceKey>,
+}
+
+#[derive(Deserialize)]
+struct SliceKey {
+    frame: usize,
+    bounds: Bounds,
+}
+
+// The frames a tag plays, in order, and how.
+fn tag_frames(tag: &Tag) -> Result<(Vec<usize>, LoopMode), String> {
+    if tag.from > tag.to {
+        return Err(format!("tag \"{}\" ends before it starts", tag.name));
+    }
+    let repeat = match &tag.repeat {
+        None => None,
+        Some(Value::Number(n)) => Some(n.as_u64()),
+        Some(Value::String(s)) => Some(s.parse::<u64>().ok()),
+        Some(_) => Some(None),
+    };
+    if repeat == Some(None) {
+        return Err(format!("tag \"{}\" has an unreadable repeat", tag.name));
+    }
+    // Playing once is the only count a clip can stop after. A ping-pong played once only goes forward.
+    let once = repeat == Some(Some(1));
+    let forward: Vec<usize> = (tag.from..=tag.to).collect();
+    match tag.direction.as_str() {
+        "" | "forward" if once => Ok((forward, LoopMode::Once)),
+        "" | "forward" => Ok((forward, LoopMode::Loop)),
+        "reverse" => Ok((forward.into_iter().rev().collect(), if once { LoopMode::Once } else { LoopMode::Loop })),
+        "pingpong" if once => Ok((forward, LoopMode::Once)),
+        "pingpong" => Ok((forward, LoopMode::PingPong)),
+        direction => Err(format!("tag \"{}\" has unknown direction \"{}\"", tag.name, direction)),
+    }
+}
+
+pub fn load(path: &str, textures: &TextureRegistry, texture: Option<&str>) -> Result<SpriteSheet, String> {
+    let source = fs::read_to_string(path).map_err(|e| format!("Could not read {}: {}", path, e))?;
+    let file: AsepriteFile = serde_json::from_str(&source).map_err(|e| format!("{}: {}", path, e))?;
+    build(file, textures, texture).map_err(|e| format!("{}: {}", path, e))
+}
+
+fn build(file: AsepriteFile, textures: &TextureRegistry, texture: Option<&str>) -> Result<SpriteSheet, String> {
+    let texture_name = match texture {
+        Some(name) => name.to_string(),
+        None => Path::new(&file.meta.image).file_stem().map(|stem| stem.to_string_lossy().to_lowercase()).unwrap_or_default(),
+    };
+    let spritesheet = textures.get(&texture_name)
+        .ok_or_else(|| format!("needs the texture \"{}\", which isn't in the manifest", texture_name))?;
+
+    if file.frames.is_object() {
+        return Err("frames are exported as a hash, export them as an array".to_string());
+    }
+    let frames: Vec<Frame> = serde_json::from_value(file.frames).map_err(|e| e.to_string())?;
+    if frames.is_empty() {
+        return Err("there are no frames".to_string());
+    }
+    if let Some(trimmed) = frames.iter().position(|frame| frame.trimmed) {
+        return Err(format!("frame {} is trimmed, export without trimming", trimmed));
+    }
+
+    let mut tags = file.meta.frame_tags;
+    if tags.is_empty() {
+        tags.push(Tag {
+            name: UNTAGGED_CLIP.to_string(),
+            from: 0,
+            to: frames.len() - 1,
+            direction: String::new(),
+            repeat: None,
+        });
+    }
+
+    let mut clips = HashMap::new();
+    let mut sources = HashMap::new();
+    for tag in &tags {
+        let (order, mode) = tag_frames(tag)?;
+        if let Some(&missing) = order.iter().find(|&&i| i >= frames.len()) {
+            return Err(format!("tag \"{}\" uses frame {}, but there are only {}", tag.name, missing, frames.len()));
+        }
+        clips.insert(tag.name.clone(), Clip {
+            frames: order.iter().map(|&i| Sprite { spritesheet, region: frames[i].frame.rect() }).collect(),
+            durations: order.iter().map(|&i| frames[i].duration).collect(),
+            mode,
+        });
+        sources.insert(tag.name.clone(), order);
+    }
+
+    let slices = file.meta.slices.into_iter()
+        .map(|slice| {
+            let mut keys: Vec<(usize, Rect)> = slice.keys.into_iter().map(|key| (key.frame, key.bounds.rect())).collect();
+            keys.sort_by_key(|&(frame, _)| frame);
+            (slice.name, keys)
+        })
+        .collect();
+
+    Ok(SpriteSheet { clips, slices, sources })
+}
@@ -0,0 +1,512 @@
+use specs::prelude::*;
+use std::collections::VecDeque;
+use rand::{Rng, thread_rng};
+use crate::components::*;
+use crate::foes::FoeDatabase;
+use crate::items::{ItemDatabase, give_item};
+use crate::levels::LevelTable;
+use super::{Gamestate, MovementCommand, PlayerCommands};
+
+// Battles are fought on their own screen while in Gamestate::Battle. The party (the player and any
+// PartyMember) and the foes from the enemy's Encounter take turns, fastest first. The party's turns
+// wait for a command, the foes pick someone to hit on their own. Afterwards the health and MP the
+// party has left go back to the world, and the player is where they were when it started.
+
+// Foes wait this long before acting, so the log can be followed.
+const FOE_TURN_MS: f32 = 700.0;
+const LOG_LINES: usize = 4;
+// After fleeing, the enemy gives the player this long to get away.
+const FLEE_GRACE_MS: f32 = 2000.0;
+const MAGIC_MP: u32 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Side {
+    Party,
+    Foes,
+}
+
+#[derive(Debug, Clone)]
+pub struct Fighter {
+    pub name: String,
+    pub side: Side,
+    // Where a party member came from, to hand back their health and MP afterwards.
+    pub entity: Option<Entity>,
+    pub sprite: Sprite,
+    pub health: Health,
+    pub stats: Stats,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BattleCommand {
+    Attack,
+    // Costs MAGIC_MP, and goes straight through defence.
+    Magic,
+    Item,
+    Flee,
+}
+
+impl BattleCommand {
+    pub const ALL: [BattleCommand; 4] = [BattleCommand::Attack, BattleCommand::Magic, BattleCommand::Item, BattleCommand::Flee];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            BattleCommand::Attack => "Attack",
+            BattleCommand::Magic => "Magic",
+            BattleCommand::Item => "Item",
+            BattleCommand::Flee => "Flee",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Outcome {
+    Victory,
+    Defeat,
+    Fled,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Phase {
+    // Picking a command for the party member whose turn it is.
+    #[default]
+    Command,
+    // Picking which foe to attack or cast at.
+    Target,
+    // Picking an item to use.
+    Item,
+    // Showing how it went until the player carries on.
+    Over(Outcome),
+}
+
+#[derive(Debug, Default)]
+pub struct Battle {
+    pub fighters: Vec<Fighter>,
+    // Indices into fighters, fastest first.
+    order: Vec<usize>,
+    turn: usize,
+    pub phase: Phase,
+    pub command: usize,
+    // The highlighted foe or item.
+    pub selected: usize,
+    pub log: VecDeque<String>,
+    // The enemy in the world that started it, and the one that asked to.
+    enemy: Option<Entity>,
+    requested: Option<Entity>,
+    experience: u32,
+    loot: Vec<(String, u32)>,
+    waited_ms: f32,
+}
+
+// Attack against defence, never less than 1, give or take a quarter.
+pub fn damage_dealt<R: Rng>(attacker: &Stats, target: &Stats, rng: &mut R) -> u32 {
+    let base = (attacker.attack * 2).saturating_sub(target.defence).max(1);
+    let spread = base / 4;
+    rng.gen_range(base - spread, base + spread + 1)
+}
+
+// Like an attack, but the target's defence doesn't help.
+pub fn magic_dealt<R: Rng>(caster: &Stats, rng: &mut R) -> u32 {
+    let base = (caster.attack * 2).max(1);
+    let spread = base / 4;
+    rng.gen_range(base - spread, base + spread + 1)
+}
+
+// Items in the inventory that can be used in battle, with how many there are.
+pub fn battle_items(inventory: &Inventory, database: &ItemDatabase) -> Vec<(String, u32)> {
+    let mut items: Vec<(String, u32)> = Vec::new();
+    for stack in &inventory.slots {
+        if database.get(&stack.item).and_then(|item| item.heal).is_none() {
+            continue;
+        }
+        match items.iter_mut().find(|(id, _)| *id == stack.item) {
+            Some((_, count)) => *count += stack.count,
+            None => items.push((stack.item.clone(), stack.count)),
+        }
+    }
+    items
+}
+
+impl Battle {
+    // Starts a battle with this enemy at the start of the next tick, unless one is already going on.
+    pub fn request(&mut self, enemy: Entity) {
+        self.requested.get_or_insert(enemy);
+    }
+
+    // The fighter whose turn it is.
+    pub fn current(&self) -> Option<usize> {
+        self.order.get(self.turn).copied()
+    }
+
+    // Foes that are still standing, in the order they are shown.
+    pub fn targets(&self) -> Vec<usize> {
+        self.alive(Side::Foes)
+    }
+
+    fn alive(&self, side: Side) -> Vec<usize> {
+        (0..self.fighters.len())
+            .filter(|&i| self.fighters[i].side == side && !self.fighters[i].health.dead())
+            .collect()
+    }
+
+    fn say(&mut self, line: String) {
+        self.log.push_back(line);
+        while self.log.len() > LOG_LINES {
+            self.log.pop_front();
+        }
+    }
+
+    fn start(&mut self, enemy: Entity, fighters: Vec<Fighter>, experience: u32, loot: Vec<(String, u32)>) {
+        let mut order: Vec<usize> = (0..fighters.len()).collect();
+        // Stable, so the party goes first when just as fast.
+        order.sort_by_key(|&i| std::cmp::Reverse(fighters[i].stats.speed));
+        let names: Vec<String> = fighters.iter().filter(|f| f.side == Side::Foes).map(|f| f.name.clone()).collect();
+        *self = Battle {
+            fighters,
+            order,
+            enemy: Some(enemy),
+            experience,
+            loot,
+            ..Battle::default()
+        };
+        self.say(format!("{} attack!", names.join(" and ")));
+    }
+
+    // Moves on to the next one still standing, or ends the battle if a side is down.
+    fn next_turn(&mut self) {
+        self.phase = Phase::Command;
+        self.selected = 0;
+        self.waited_ms = 0.0;
+        if self.alive(Side::Foes).is_empty() {
+            self.say(format!("Victory! The party gains {} experience.", self.experience));
+            self.phase = Phase::Over(Outcome::Victory);
+            return;
+        }
+        if self.alive(Side::Party).is_empty() {
+            self.say("The party has fallen...".to_string());
+            self.phase = Phase::Over(Outcome::Defeat);
+            return;
+        }
+        for _ in 0..self.order.len() {
+            self.turn = (self.turn + 1) % self.order.len();
+            if !self.fighters[self.order[self.turn]].health.dead() {
+                break;
+            }
+        }
+    }
+
+    fn attack(&mut self, attacker: usize, target: usize) {
+        let amount = damage_dealt(&self.fighters[attacker].stats, &self.fighters[target].stats, &mut thread_rng());
+        let line = format!("{} hits {} for {}.", self.fighters[attacker].name, self.fighters[target].name, amount);
+        self.hurt(target, amount, line);
+    }
+
+    fn cast(&mut self, caster: usize, target: usize) {
+        let amount = magic_dealt(&self.fighters[caster].stats, &mut thread_rng());
+        self.fighters[caster].stats.mp -= MAGIC_MP;
+        let line = format!("{} casts a spell on {} for {}.", self.fighters[caster].name, self.fighters[target].name, amount);
+        self.hurt(target, amount, line);
+    }
+
+    fn hurt(&mut self, target: usize, amount: u32, line: String) {
+        let health = &mut self.fighters[target].health;
+        health.current = health.current.saturating_sub(amount);
+        let fell = health.dead();
+        self.say(line);
+        if fell {
+            self.say(format!("{} is defeated.", self.fighters[target].name));
+        }
+    }
+
+    // The faster the party member is than the fastest foe, the likelier it works.
+    fn flee(&self, actor: usize) -> bool {
+        let fastest = self.alive(Side::Foes).iter().map(|&i| self.fighters[i].stats.speed).max().unwrap_or(0);
+        let difference = self.fighters[actor].stats.speed as f64 - fastest as f64;
+        let chance = (0.5 + 0.1 * difference).clamp(0.1, 0.9);
+        thread_rng().gen_bool(chance)
+    }
+}
+
+pub struct Battles;
+
+impl<'a> System<'a> for Battles {
+
+    type SystemData = (
+        Entities<'a>,
+        WriteExpect<'a, Battle>,
+        WriteExpect<'a, Gamestate>,
+        ReadExpect<'a, VecDeque<Option<MovementCommand>>>,
+        ReadExpect<'a, Option<PlayerCommands>>,
+        ReadExpect<'a, DeltaTime>,
+        ReadExpect<'a, FoeDatabase>,
+        ReadExpect<'a, ItemDatabase>,
+        ReadExpect<'a, LevelTable>,
+        WriteExpect<'a, Notifications>,
+        ReadStorage<'a, KeyboardControlled>,
+        ReadStorage<'a, PartyMember>,
+        ReadStorage<'a, Name>,
+        ReadStorage<'a, Sprite>,
+        ReadStorage<'a, Encounter>,
+        WriteStorage<'a, Health>,
+        WriteStorage<'a, Stats>,
+        WriteStorage<'a, Inventory>,
+        WriteStorage<'a, Hostile>,
+        WriteStorage<'a, Velocity>,
+    );
+
+    fn run(&mut self, (
+        entities,
+        mut battle,
+        mut gamestate,
+        movementcommands,
+        playercommands,
+        delta,
+        foe_database,
+        item_database,
+        levels,
+        mut notifications,
+        keyboard_controlled,
+        party_member,
+        name,
+        sprite,
+        encounter,
+        mut health,
+        mut stats,
+        mut inventory,
+        mut hostile,
+        mut velocity,
+    ): Self::SystemData) {
+        if *gamestate != Gamestate::Battle {
+            let enemy = match battle.requested.take() {
+                Some(enemy) if *gamestate == Gamestate::Running => enemy,
+                _ => return,
+            };
+            let foes: Vec<_> = encounter.get(enemy).map_or(Vec::new(), |encounter| {
+                encounter.foes.iter().filter_map(|id| foe_database.get(id)).collect()
+            });
+            if foes.is_empty() {
+                return;
+            }
+
+            let mut fighters = Vec::new();
+            for (entity, hp, st, look, player, _) in (&entities, &health, &stats, &sprite, (&keyboard_controlled).maybe(), keyboard_controlled.mask() | party_member.mask()).join() {
+                let fallback = if player.is_some() { "You" } else { "Ally" };
+                fighters.push(Fighter {
+                    name: name.get(entity).map_or(fallback.to_string(), |name| name.0.clone()),
+                    side: Side::Party,
+                    entity: Some(entity),
+                    sprite: *look,
+                    health: hp.clone(),
+                    stats: st.clone(),
+                });
+            }
+            let mut loot = Vec::new();
+            for foe in &foes {
+                fighters.push(Fighter {
+                    name: foe.name.clone(),
+                    side: Side::Foes,
+                    entity: None,
+                    sprite: foe.sprite,
+                    health: Health::new(foe.health),
+                    stats: foe.stats.clone(),
+                });
+                loot.extend(foe.loot.roll());
+            }
+            let experience = foes.iter().map(|foe| foe.experience).sum();
+            battle.start(enemy, fighters, experience, loot);
+
+            // Nobody keeps walking once it's over.
+            for (_, vel) in (&keyboard_controlled, &mut velocity).join() {
+                vel.direction.clear();
+            }
+            *gamestate = Gamestate::Battle;
+            return;
+        }
+
+        let actor = match battle.current() {
+            Some(actor) => actor,
+            None => {
+                *gamestate = Gamestate::Running;
+                return;
+            },
+        };
+        let moves: Vec<Direction> = movementcommands.iter()
+            .filter_map(|command| match command {
+                Some(MovementCommand::Move(direction)) => Some(*direction),
+                _ => None,
+            })
+            .collect();
+        let confirm = matches!(*playercommands, Some(PlayerCommands::Interact));
+        let cancel = matches!(*playercommands, Some(PlayerCommands::Cancel));
+        let player_inventory = (&keyboard_controlled, &mut inventory).join().map(|(_, inv)| inv).next();
+
+        match battle.phase {
+            Phase::Over(outcome) => {
+                if !confirm {
+                    return;
+                }
+                for fighter in battle.fighters.iter().filter(|fighter| fighter.side == Side::Party) {
+                    let entity = match fighter.entity {
+                        Some(entity) => entity,
+                        None => continue,
+                    };
+                    if let Some(hp) = health.get_mut(entity) {
+                        // Whoever fell gets back up unless the whole party did, or Vitals would take
+                        // them for dead.
+                        hp.current = match outcome {
+                            Outcome::Defeat => fighter.health.current,
+                            _ => fighter.health.current.max(1),
+                        };
+                        // A moment to get away from whatever is nearby.
+                        hp.invulnerable_ms = INVULNERABLE_MS;
+                    }
+                    let st = match stats.get_mut(entity) {
+                        Some(st) => st,
+                        None => continue,
+                    };
+                    st.mp = fighter.stats.mp;
+                    if outcome == Outcome::Victory {
+                        st.experience += battle.experience;
+                        for level in levels.level_up(st, health.get_mut(entity)) {
+                            notifications.push(format!("{} reached level {}!", fighter.name, level));
+                        }
+                    }
+                }
+                match outcome {
+                    Outcome::Victory => {
+                        if let Some(enemy) = battle.enemy {
+                            entities.delete(enemy).ok();
+                        }
+                        if let Some(inv) = player_inventory {
+                            for (item, count) in &battle.loot {
+                                give_item(inv, &item_database, &mut notifications, item, *count);
+                            }
+                        }
+                        *gamestate = Gamestate::Running;
+                    },
+                    Outcome::Fled => {
+                        if let Some(foe) = battle.enemy.and_then(|enemy| hostile.get_mut(enemy)) {
+                            foe.state = HostileState::Returning;
+                            foe.cooldown_ms = FLEE_GRACE_MS;
+                        }
+                        *gamestate = Gamestate::Running;
+                    },
+                    // The player's health is gone too, so Vitals would say the same.
+                    Outcome::Defeat => *gamestate = Gamestate::GameOver,
+                }
+                *battle = Battle::default();
+            },
+
+            _ if battle.fighters[actor].side == Side::Foes => {
+                battle.waited_ms += delta.0.as_secs_f32() * 1000.0;
+                if battle.waited_ms < FOE_TURN_MS {
+                    return;
+                }
+                let party = battle.alive(Side::Party);
+                let target = party[thread_rng().gen_range(0, party.len())];
+                battle.attack(actor, target);
+                battle.next_turn();
+            },
+
+            Phase::Command => {
+                let count = BattleCommand::ALL.len();
+                for direction in moves {
+                    match direction {
+                        Direction::Up => battle.command = (battle.command + count - 1) % count,
+                        Direction::Down => battle.command = (battle.command + 1) % count,
+                        _ => {},
+                    }
+                }
+                if !confirm {
+                    return;
+                }
+                match BattleCommand::ALL[battle.command] {
+                    BattleCommand::Attack => {
+                        battle.selected = 0;
+                        battle.phase = Phase::Target;
+                    },
+                    BattleCommand::Magic => {
+                        if battle.fighters[actor].stats.mp < MAGIC_MP {
+                            battle.say(format!("Not enough MP, magic takes {}.", MAGIC_MP));
+                        } else {
+                            battle.selected = 0;
+                            battle.phase = Phase::Target;
+                        }
+                    },
+                    BattleCommand::Item => {
+                        if player_inventory.is_none_or(|inv| battle_items(inv, &item_database).is_empty()) {
+                            battle.say("There is nothing to use.".to_string());
+                        } else {
+                            battle.selected = 0;
+                            battle.phase = Phase::Item;
+                        }
+                    },
+                    BattleCommand::Flee => {
+                        if battle.flee(actor) {
+                            battle.say("The party got away.".to_string());
+                            battle.phase = Phase::Over(Outcome::Fled);
+                        } else {
+                            battle.say("Couldn't get away!".to_string());
+                            battle.next_turn();
+                        }
+                    },
+                }
+            },
+
+            Phase::Target => {
+                let targets = battle.targets();
+                for direction in moves {
+                    match direction {
+                        Direction::Left | Direction::Up => battle.selected = (battle.selected + targets.len() - 1) % targets.len(),
+                        Direction::Right | Direction::Down => battle.selected = (battle.selected + 1) % targets.len(),
+                    }
+                }
+                if cancel {
+                    battle.phase = Phase::Command;
+                } else if confirm {
+                    let target = targets[battle.selected.min(targets.len() - 1)];
+                    if BattleCommand::ALL[battle.command] == BattleCommand::Magic {
+                        battle.cast(actor, target);
+                    } else {
+                        battle.attack(actor, target);
+                    }
+                    battle.next_turn();
+                }
+            },
+
+            Phase::Item => {
+                let inv = match player_inventory {
+                    Some(inv) => inv,
+                    None => {
+                        battle.phase = Phase::Command;
+                        return;
+                    },
+                };
+                let items = battle_items(inv, &item_database);
+                if items.is_empty() {
+                    battle.phase = Phase::Command;
+                    return;
+                }
+                for direction in moves {
+                    match direction {
+                        Direction::Up => battle.selected = battle.selected.saturating_sub(1),
+                        Direction::Down => battle.selected = (battle.selected + 1).min(items.len() - 1),
+                        _ => {},
+                    }
+                }
+                if cancel {
+                    battle.phase = Phase::Command;
+                } else if confirm {
+                    let (id, _) = &items[battle.selected.min(items.len() - 1)];
+                    if let Some(item) = item_database.get(id) {
+                        inv.remove(id, 1);
+                        let health = &mut battle.fighters[actor].health;
+                        health.current = (health.current + item.heal.unwrap_or(0)).min(health.max);
+                        let line = format!("{} uses the {}.", battle.fighters[actor].name, item.name);
+                        battle.say(line);
+                    }
+                    battle.next_turn();
+                }
+            },
+        }
+    }
+}
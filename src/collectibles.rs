@@ -1,6 +1,7 @@
-use specs::{System, ReadStorage, join::Join, Entities};
+use specs::{System, ReadStorage, WriteStorage, ReadExpect, WriteExpect, join::Join, Entities};
 use sdl2::rect::{Rect};
 use crate::components::*;
+use crate::items::{ItemDatabase, give_item};
 
 pub struct Collectibles;
 
@@ -12,24 +13,27 @@ impl<'a> System<'a> for Collectibles {
         ReadStorage<'a, CollisionBox>,
         ReadStorage<'a, Collectible>,
         ReadStorage<'a, Playable>,
+        WriteStorage<'a, Inventory>,
+        ReadExpect<'a, ItemDatabase>,
+        WriteExpect<'a, Notifications>,
     );
 
-    fn run(&mut self, data: Self::SystemData) {
+    fn run(&mut self, (entities, position, collisionbox, collectible, playable, mut inventory, item_database, mut notifications): Self::SystemData) {
         
-        for (pos, col, _) in (&data.1, &data.2, &data.4).join() {
+        for (pos, col, _, inv) in (&position, &collisionbox, &playable, &mut inventory).join() {
             let player_rect = Rect::from_center(pos.0, col.width, col.height); 
 
-            (&data.0, &data.1, &data.2, &data.3).join()
-            .for_each(
-                |(entity, obj_pos, obj_col, _)| {
-                    let obj_rect = Rect::from_center(obj_pos.0, obj_col.width, obj_col.height);
-                    if obj_rect.intersection(player_rect).is_some(){
-                        data.0.delete(entity).ok();
-                        println!("Collected fruit!");
-                    }
+            for (entity, obj_pos, obj_col, item) in (&entities, &position, &collisionbox, &collectible).join() {
+                let obj_rect = Rect::from_center(obj_pos.0, obj_col.width, obj_col.height);
+                if obj_rect.intersection(player_rect).is_none() {
+                    continue;
                 }
-            );
+                // Leave the item lying there if there's no room for it.
+                let has_room = item_database.get(&item.item).is_some_and(|def| inv.room_for(def) > 0);
+                if has_room && give_item(inv, &item_database, &mut notifications, &item.item, 1) == 0 {
+                    entities.delete(entity).ok();
+                }
+            }
         }
     }
 }
-
@@ -0,0 +1,214 @@
+use crate::components::*;
+use std::{fs, path::Path};
+use specs::prelude::*;
+use sdl2::rect::{Point, Rect};
+
+// Collision file format, one rectangle per line in world coordinates:
+//
+// -120 40 64 32                    Left, top, width and height.
+//
+// Lines starting with // are comments.
+
+// Smallest rectangle that can be drawn, anything less is taken to be a click.
+const MIN_SIZE: u32 = 4;
+// How close to the bottom right corner counts as grabbing it to resize.
+pub const HANDLE_SIZE: u32 = 8;
+
+enum Drag {
+    Draw { start: Point, current: Point },
+    Move { entity: Entity, offset: Point },
+    Resize { entity: Entity },
+}
+
+// Lets the designer draw static collision rectangles over the map while in Gamestate::CollisionEditor.
+// Left drag on empty ground draws a new one. Clicking one selects it, then dragging moves it or, from
+// the bottom right corner, resizes it. Right click or Delete removes one. All points are in world
+// coordinates.
+#[derive(Default)]
+pub struct CollisionEditor {
+    pub selected: Option<Entity>,
+    drag: Option<Drag>,
+}
+
+pub fn collider_rect(pos: &Position, col: &CollisionBox) -> Rect {
+    Rect::from_center(pos.0, col.width, col.height)
+}
+
+pub fn handle_rect(rect: Rect) -> Rect {
+    Rect::new(
+        rect.right() - HANDLE_SIZE as i32,
+        rect.bottom() - HANDLE_SIZE as i32,
+        HANDLE_SIZE,
+        HANDLE_SIZE,
+    )
+}
+
+// The rectangle between two corners, in whichever order they come.
+fn span(a: Point, b: Point) -> Rect {
+    Rect::new(
+        a.x().min(b.x()),
+        a.y().min(b.y()),
+        (a.x() - b.x()).unsigned_abs(),
+        (a.y() - b.y()).unsigned_abs(),
+    )
+}
+
+fn collider_at(world: &World, point: Point) -> Option<(Entity, Rect)> {
+    let entities = world.entities();
+    let position = world.read_storage::<Position>();
+    let collision = world.read_storage::<CollisionBox>();
+    let colliders = world.read_storage::<StaticCollider>();
+    // The last one drawn is on top.
+    (&entities, &position, &collision, &colliders).join()
+        .map(|(entity, pos, col, _)| (entity, collider_rect(pos, col)))
+        .filter(|(_, rect)| rect.contains_point(point))
+        .last()
+}
+
+pub fn spawn_collider(world: &mut World, rect: Rect) -> Entity {
+    world
+        .create_entity()
+        .with(Position(rect.center()))
+        .with(CollisionBox {
+            width: rect.width(),
+            height: rect.height(),
+        })
+        .with(Unplayable)
+        .with(StaticCollider)
+        .build()
+}
+
+impl CollisionEditor {
+    // The rectangle being drawn, if any.
+    pub fn drawing(&self) -> Option<Rect> {
+        match self.drag {
+            Some(Drag::Draw { start, current }) => Some(span(start, current)),
+            _ => None,
+        }
+    }
+
+    pub fn mouse_down(&mut self, world: &mut World, point: Point) {
+        let selected = self.selected.and_then(|entity| {
+            let position = world.read_storage::<Position>();
+            let collision = world.read_storage::<CollisionBox>();
+            Some((entity, collider_rect(position.get(entity)?, collision.get(entity)?)))
+        });
+        self.drag = match selected {
+            Some((entity, rect)) if handle_rect(rect).contains_point(point) => Some(Drag::Resize { entity }),
+            _ => match collider_at(world, point) {
+                Some((entity, rect)) => {
+                    self.selected = Some(entity);
+                    Some(Drag::Move { entity, offset: point - rect.center() })
+                },
+                None => {
+                    self.selected = None;
+                    Some(Drag::Draw { start: point, current: point })
+                },
+            },
+        };
+    }
+
+    pub fn mouse_moved(&mut self, world: &mut World, point: Point) {
+        match &mut self.drag {
+            Some(Drag::Draw { current, .. }) => *current = point,
+            Some(Drag::Move { entity, offset }) => {
+                if let Some(pos) = world.write_storage::<Position>().get_mut(*entity) {
+                    pos.0 = point - *offset;
+                }
+            },
+            Some(Drag::Resize { entity }) => {
+                let mut position = world.write_storage::<Position>();
+                let mut collision = world.write_storage::<CollisionBox>();
+                if let (Some(pos), Some(col)) = (position.get_mut(*entity), collision.get_mut(*entity)) {
+                    // The top left corner stays put.
+                    let top_left = collider_rect(pos, col).top_left();
+                    let rect = Rect::new(
+                        top_left.x(),
+                        top_left.y(),
+                        ((point.x() - top_left.x()).max(0) as u32).max(MIN_SIZE),
+                        ((point.y() - top_left.y()).max(0) as u32).max(MIN_SIZE),
+                    );
+                    pos.0 = rect.center();
+                    col.width = rect.width();
+                    col.height = rect.height();
+                }
+            },
+            None => {},
+        }
+    }
+
+    pub fn mouse_up(&mut self, world: &mut World) {
+        if let Some(Drag::Draw { start, current }) = self.drag.take() {
+            let rect = span(start, current);
+            if rect.width() >= MIN_SIZE && rect.height() >= MIN_SIZE {
+                self.selected = Some(spawn_collider(world, rect));
+            }
+        }
+    }
+
+    pub fn delete_at(&mut self, world: &mut World, point: Point) {
+        if let Some((entity, _)) = collider_at(world, point) {
+            self.delete(world, entity);
+        }
+    }
+
+    pub fn delete_selected(&mut self, world: &mut World) {
+        if let Some(entity) = self.selected {
+            self.delete(world, entity);
+        }
+    }
+
+    fn delete(&mut self, world: &mut World, entity: Entity) {
+        world.delete_entity(entity).ok();
+        if self.selected == Some(entity) {
+            self.selected = None;
+        }
+        self.drag = None;
+    }
+}
+
+pub fn save(world: &World, path: &str) -> Result<(), String> {
+    let position = world.read_storage::<Position>();
+    let collision = world.read_storage::<CollisionBox>();
+    let colliders = world.read_storage::<StaticCollider>();
+
+    let mut contents = String::from("// Static collision rectangles: left top width height. See src/collision_editor.rs.\n");
+    for (pos, col, _) in (&position, &collision, &colliders).join() {
+        let rect = collider_rect(pos, col);
+        contents.push_str(&format!("{} {} {} {}\n", rect.x(), rect.y(), rect.width(), rect.height()));
+    }
+    fs::write(path, contents).map_err(|e| format!("Could not save collision file {}: {}", path, e))
+}
+
+// Replaces the static colliders in the world with the ones in the file. A missing file means there are none.
+pub fn load(world: &mut World, path: &str) -> Result<(), String> {
+    let contents = if Path::new(path).exists() {
+        fs::read_to_string(path).map_err(|e| format!("Could not read collision file {}: {}", path, e))?
+    } else {
+        String::new()
+    };
+
+    let mut rects = Vec::new();
+    for (line_no, line) in contents.lines().enumerate().map(|(i, l)| (i + 1, l.trim())) {
+        if line.is_empty() || line.starts_with("//") {
+            continue;
+        }
+        let numbers = line.split_whitespace()
+            .map(str::parse::<i32>)
+            .collect::<Result<Vec<i32>, _>>()
+            .map_err(|_| format!("{}: line {}: could not parse \"{}\"", path, line_no, line))?;
+        match numbers[..] {
+            [x, y, w, h] if w > 0 && h > 0 => rects.push(Rect::new(x, y, w as u32, h as u32)),
+            _ => return Err(format!("{}: line {}: expected left, top, width and height", path, line_no)),
+        }
+    }
+
+    let existing: Vec<Entity> = (&world.entities(), &world.read_storage::<StaticCollider>()).join()
+        .map(|(entity, _)| entity)
+        .collect();
+    world.delete_entities(&existing).ok();
+    for rect in rects {
+        spawn_collider(world, rect);
+    }
+    Ok(())
+}
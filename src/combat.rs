@@ -0,0 +1,143 @@
+use specs::{System, ReadStorage, WriteStorage, ReadExpect, join::Join, Entities};
+use sdl2::rect::{Rect, Point};
+use crate::animations::SpriteSheets;
+use crate::components::*;
+use crate::update_interaction::place_in_front;
+use super::{Gamestate, PlayerCommands};
+
+// The swing sheet has a clip swing_up, swing_down, swing_left and swing_right. A slice named like the
+// clip is the part of the frame that hurts, otherwise the whole frame does.
+const SWING_SHEET: &str = "swing";
+// A swing does a quarter of the swinger's attack, but never less than this.
+const SWING_DAMAGE: u32 = 1;
+const SWING_ATTACK_DIVISOR: u32 = 4;
+// Pixels per tick, for KNOCKBACK_MS.
+const SWING_KNOCKBACK: i32 = 8;
+
+pub struct Combat;
+
+fn swing_clip(direction: Direction) -> &'static str {
+    match direction {
+        Direction::Up => "swing_up",
+        Direction::Down => "swing_down",
+        Direction::Left => "swing_left",
+        Direction::Right => "swing_right",
+    }
+}
+
+impl<'a> System<'a> for Combat {
+
+    type SystemData = (
+        Entities<'a>,
+        ReadExpect<'a, Option<PlayerCommands>>,
+        ReadExpect<'a, Gamestate>,
+        ReadExpect<'a, SpriteSheets>,
+        ReadExpect<'a, AnimationEvents>,
+        ReadStorage<'a, KeyboardControlled>,
+        ReadStorage<'a, Facing>,
+        ReadStorage<'a, CollisionBox>,
+        ReadStorage<'a, Velocity>,
+        ReadStorage<'a, Health>,
+        ReadStorage<'a, Stats>,
+        WriteStorage<'a, Position>,
+        WriteStorage<'a, Damage>,
+        WriteStorage<'a, Hitbox>,
+        WriteStorage<'a, Sprite>,
+        WriteStorage<'a, EntityAnimation>,
+    );
+
+    fn run(&mut self, (
+        entities,
+        playercommands,
+        gamestate,
+        sheets,
+        events,
+        keyboard_controlled,
+        facing,
+        collisionbox,
+        velocity,
+        health,
+        stats,
+        mut position,
+        mut damage,
+        mut hitbox,
+        mut sprite,
+        mut entanimation,
+    ): Self::SystemData) {
+
+        // A swing is over once its animation is.
+        for &entity in &events.finished {
+            if hitbox.contains(entity) {
+                entities.delete(entity).ok();
+            }
+        }
+
+        if *gamestate == Gamestate::Running {
+            if let Some(PlayerCommands::Attack) = &*playercommands {
+                for (player, face, col, _) in (&entities, &facing, &collisionbox, &keyboard_controlled).join() {
+                    // One swing at a time, though one that just ended makes room for the next.
+                    if (&entities, &hitbox).join().any(|(swing, hb)| hb.owner == player && !events.finished.contains(&swing)) {
+                        continue;
+                    }
+                    let mut anim = match sheets.entity_animation(SWING_SHEET, swing_clip(face.direction)) {
+                        Ok(anim) => anim,
+                        Err(e) => {
+                            println!("{}", e);
+                            continue;
+                        }
+                    };
+                    anim.clip.mode = LoopMode::Once;
+                    anim.playback.play();
+                    let (width, height) = anim.sprite().region.size();
+                    let area = sheets.slice(SWING_SHEET, swing_clip(face.direction), swing_clip(face.direction), anim.playback.frame).unwrap_or_else(|| Rect::new(0, 0, width, height));
+                    let sprite_offset = Point::new(width as i32 / 2, height as i32 / 2) - area.center();
+                    let mut rect = Rect::new(0, 0, area.width(), area.height());
+                    place_in_front(&mut rect, position.get(player).map_or(Point::new(0, 0), |pos| pos.0), col, face.direction);
+
+                    let swing = entities.create();
+                    sprite.insert(swing, anim.sprite()).ok();
+                    entanimation.insert(swing, anim).ok();
+                    position.insert(swing, Position(rect.center() + sprite_offset)).ok();
+                    hitbox.insert(swing, Hitbox {
+                        owner: player,
+                        direction: face.direction,
+                        rect,
+                        sprite_offset,
+                        damage: stats.get(player).map_or(SWING_DAMAGE, |st| (st.attack / SWING_ATTACK_DIVISOR).max(SWING_DAMAGE)),
+                        knockback: SWING_KNOCKBACK,
+                        hit: Vec::new(),
+                    }).ok();
+                }
+            }
+        }
+
+        // Swings follow whoever swings them.
+        for (entity, hb) in (&entities, &mut hitbox).join() {
+            let (owner_pos, owner_col) = match (position.get(hb.owner), collisionbox.get(hb.owner)) {
+                (Some(pos), Some(col)) => (pos.0, col),
+                _ => {
+                    entities.delete(entity).ok();
+                    continue;
+                }
+            };
+            place_in_front(&mut hb.rect, owner_pos, owner_col, hb.direction);
+
+            // Only things that can be hurt or move about are hit, not walls, chests or items on the ground.
+            for (target, pos, col, hp, vel) in (&entities, &position, &collisionbox, (&health).maybe(), (&velocity).maybe()).join() {
+                if target == hb.owner || hb.hit.contains(&target) || (hp.is_none() && vel.is_none()) {
+                    continue;
+                }
+                if hb.rect.has_intersection(Rect::from_center(pos.0, col.width, col.height)) {
+                    hb.hit.push(target);
+                    damage.insert(target, Damage {
+                        amount: hb.damage,
+                        knockback: Some((hb.direction, hb.knockback)),
+                    }).ok();
+                }
+            }
+        }
+        for (entity, hb) in (&entities, &hitbox).join() {
+            position.insert(entity, Position(hb.rect.center() + hb.sprite_offset)).ok();
+        }
+    }
+}
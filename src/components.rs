@@ -1,16 +1,27 @@
+mod animation;
+mod combat;
 mod interactable_objects;
+mod loot;
+mod signals;
+pub use animation::*;
+pub use combat::*;
 use interactable_objects::*;
+pub use loot::*;
+pub use signals::*;
+use crate::items::ItemDefinition;
+use crate::textures::TextureHandle;
 
-use std::{fmt::Debug, collections::VecDeque, fs::File, io::{self, Read}};
+use std::{fmt::Debug, collections::{BTreeMap, VecDeque}, io, time::{Duration, Instant}};
 use specs_derive::Component;
 use specs::prelude::*;
 use sdl2::rect::{Rect, Point};
+use serde::{Deserialize, Serialize};
 use rand::distributions::{Standard, Distribution};
 use rand::Rng;
 use io::{Lines, BufRead, BufReader};
 use regex::Regex;
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub enum Size3 {
     Small,
     Medium,
@@ -23,6 +34,17 @@ impl Default for Size3 {
     }
 }
 
+impl Size3 {
+    pub fn from_token(token: &str) -> Option<Self> {
+        match token {
+            "small" => Some(Size3::Small),
+            "medium" => Some(Size3::Medium),
+            "large" => Some(Size3::Large),
+            _ => None,
+        }
+    }
+}
+
 
 #[derive(Debug, Component, Clone)]
 #[storage(VecStorage)]
@@ -32,11 +54,14 @@ pub struct Dialogue {
     pub show: bool,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Dialogue_Single_item {
     pub speaker_name: String,
     pub dialogue_text: String,
-    pub background_size: Size3,
+    // None lets the renderer pick the smallest box the text fits in.
+    pub background_size: Option<Size3>,
+    // Extra milliseconds to wait once the given number of characters have been revealed.
+    pub pauses: Vec<(usize, u32)>,
 }
 
 #[derive(Debug, Component, Clone)]
@@ -134,8 +159,95 @@ impl Interactable {
     
 }
 
-#[derive(Debug, Component, Default)]
-pub struct Collectible;
+// Equipped item ids, by slot name.
+#[derive(Component, Debug, Clone, Default, Serialize, Deserialize)]
+#[storage(VecStorage)]
+pub struct Equipment {
+    pub slots: BTreeMap<String, String>,
+}
+
+// An item lying in the world, picked up by walking over it.
+#[derive(Debug, Component, Clone, Default, Serialize, Deserialize)]
+pub struct Collectible {
+    pub item: String,
+}
+
+// An item that stays where it is until the player interacts with it.
+#[derive(Component, Debug, Clone, Serialize, Deserialize)]
+#[storage(VecStorage)]
+pub struct Pickup {
+    pub item: String,
+    pub count: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ItemStack {
+    pub item: String,
+    pub count: u32,
+}
+
+#[derive(Component, Debug, Clone, Serialize, Deserialize)]
+#[storage(VecStorage)]
+pub struct Inventory {
+    pub slots: Vec<ItemStack>,
+    pub capacity: usize,
+}
+
+impl Inventory {
+    pub fn new(capacity: usize) -> Self {
+        Inventory {
+            slots: Vec::new(),
+            capacity,
+        }
+    }
+
+    // Tops up existing stacks before starting new ones. Returns how many did not fit.
+    pub fn add(&mut self, item: &ItemDefinition, mut count: u32) -> u32 {
+        for stack in self.slots.iter_mut().filter(|stack| stack.item == item.id) {
+            let room = item.stack_size.saturating_sub(stack.count);
+            let added = room.min(count);
+            stack.count += added;
+            count -= added;
+        }
+        while count > 0 && self.slots.len() < self.capacity {
+            let added = item.stack_size.min(count);
+            self.slots.push(ItemStack { item: item.id.clone(), count: added });
+            count -= added;
+        }
+        count
+    }
+
+    pub fn room_for(&self, item: &ItemDefinition) -> u32 {
+        let in_stacks: u32 = self.slots.iter()
+            .filter(|stack| stack.item == item.id)
+            .map(|stack| item.stack_size.saturating_sub(stack.count))
+            .sum();
+        let free_slots = self.capacity.saturating_sub(self.slots.len()) as u32;
+        in_stacks + free_slots * item.stack_size
+    }
+
+    // Takes items from the last stacks first. Returns how many were actually removed.
+    pub fn remove(&mut self, id: &str, count: u32) -> u32 {
+        let mut removed = 0;
+        for stack in self.slots.iter_mut().rev().filter(|stack| stack.item == id) {
+            let taken = stack.count.min(count - removed);
+            stack.count -= taken;
+            removed += taken;
+            if removed == count {
+                break;
+            }
+        }
+        self.slots.retain(|stack| stack.count > 0);
+        removed
+    }
+
+    pub fn count(&self, id: &str) -> u32 {
+        self.slots.iter()
+            .filter(|stack| stack.item == id)
+            .map(|stack| stack.count)
+            .sum()
+    }
+}
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Direction {
@@ -198,6 +310,11 @@ impl CollisionBox {
 #[storage(NullStorage)]
 pub struct KeyboardControlled;
 
+// Invisible walls placed with the collision editor, saved to the map's collision file.
+#[derive(Component, Debug, Default)]
+#[storage(NullStorage)]
+pub struct StaticCollider;
+
 #[derive(Component, Debug, Default)]
 #[storage(NullStorage)]
 pub struct NPCWalker;
@@ -222,46 +339,155 @@ pub struct Velocity {
 #[derive(Component, Debug, Clone, Copy)]
 #[storage(VecStorage)]
 pub struct Sprite {
-    pub spritesheet: usize,
+    pub spritesheet: TextureHandle,
     pub region: Rect,
 }
 
-#[derive(Component, Debug, Clone)]
-#[storage(VecStorage)]
-pub struct MovementAnimation {
-    pub current_frame: usize,
-    pub up_frames: Vec<Sprite>,
-    pub right_frames: Vec<Sprite>,
-    pub down_frames: Vec<Sprite>,
-    pub left_frames: Vec<Sprite>,
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Gamestate {
+    Running,
+    Pause,
+    Menu,
+    Dialogue,
+    CollisionEditor,
+    LevelEditor,
+    // The player has died. Interact goes back to the last save.
+    GameOver,
+    // Fighting the foes of an Encounter, see src/battle.rs.
+    Battle,
+    // Looking at the party's stats.
+    Status,
 }
 
-#[derive(Component, Debug, Clone)]
-#[storage(VecStorage)]
-pub struct EntityAnimation {
-    pub current_frame: usize,
-    pub frames: Vec<Sprite>,
+// Layout of the dialogue line currently on screen, worked out by the renderer since it needs the font.
+// `revealed` counts characters of the whole line that the typewriter has shown so far.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Dialogue_Helper {
+    pub text: String,
+    pub choices: usize,
+    pub size: Size3,
+    pub pages: Vec<Vec<String>>,
+    pub page: usize,
+    pub pauses: Vec<(usize, u32)>,
+    pub revealed: usize,
+    pub next_char_at: Option<Instant>,
 }
 
-impl Default for EntityAnimation {
+impl Dialogue_Helper {
+    pub fn on_last_page(&self) -> bool {
+        self.page + 1 >= self.pages.len()
+    }
+
+    pub fn next_page(&mut self) -> bool {
+        if self.on_last_page() {
+            return false;
+        }
+        self.page += 1;
+        self.revealed = self.page_start(self.page);
+        self.next_char_at = None;
+        true
+    }
+
+    // Wrapped lines were split on single spaces, so each one is followed by one more character.
+    fn page_start(&self, page: usize) -> usize {
+        self.pages[..page].iter()
+            .flatten()
+            .map(|line| line.chars().count() + 1)
+            .sum()
+    }
+
+    pub fn page_end(&self) -> usize {
+        let lines = match self.pages.get(self.page) {
+            Some(lines) => lines,
+            None => return 0,
+        };
+        let start = self.page_start(self.page);
+        let length: usize = lines.iter().map(|line| line.chars().count() + 1).sum();
+        start + length.saturating_sub(1)
+    }
+
+    pub fn page_revealed(&self) -> bool {
+        !self.pages.is_empty() && self.revealed >= self.page_end()
+    }
+
+    pub fn reveal_page(&mut self) {
+        self.revealed = self.page_end();
+    }
+
+    pub fn pause_after(&self, revealed: usize) -> u32 {
+        self.pauses.iter()
+            .filter(|(at, _)| *at == revealed)
+            .map(|(_, ms)| ms)
+            .sum()
+    }
+
+    // The part of each line on the current page that has been revealed.
+    pub fn visible_lines(&self) -> Vec<String> {
+        let mut offset = self.page_start(self.page);
+        let mut visible = Vec::new();
+        for line in self.pages.get(self.page).into_iter().flatten() {
+            let shown = self.revealed.saturating_sub(offset);
+            visible.push(line.chars().take(shown).collect());
+            offset += line.chars().count() + 1;
+        }
+        visible
+    }
+
+    // Forces the renderer to lay out the next line from scratch.
+    pub fn reset(&mut self) {
+        *self = Dialogue_Helper::default();
+    }
+}
+
+// Short messages shown at the top of the screen, like what was just found in a chest.
+#[derive(Debug, Default)]
+pub struct Notifications {
+    pub messages: VecDeque<(String, Instant)>,
+}
+
+impl Notifications {
+    pub const DURATION: Duration = Duration::from_secs(3);
+    const MAX_SHOWN: usize = 5;
+
+    pub fn push(&mut self, message: String) {
+        self.messages.retain(|(_, at)| at.elapsed() < Self::DURATION);
+        if self.messages.len() >= Self::MAX_SHOWN {
+            self.messages.pop_front();
+        }
+        self.messages.push_back((message, Instant::now()));
+    }
+
+    pub fn current(&self) -> impl Iterator<Item = &str> {
+        self.messages.iter()
+            .filter(|(_, at)| at.elapsed() < Self::DURATION)
+            .map(|(message, _)| message.as_str())
+    }
+}
+
+pub struct DialogueSettings {
+    pub characters_per_second: u32,
+}
+
+impl Default for DialogueSettings {
     fn default() -> Self {
-        EntityAnimation{
-            current_frame: 0,
-            frames: Vec::new(),
+        DialogueSettings {
+            characters_per_second: 40,
         }
     }
 }
-#[derive(Copy, Clone, Debug, PartialEq)]
-pub enum Gamestate {
-    Running,
-    Pause,
-    Menu,
-    Dialogue,
+
+pub struct DialogueBoxes {
+    pub small: Sprite,
+    pub medium: Sprite,
+    pub large: Sprite,
 }
 
-#[derive(Debug, Clone, PartialEq)]
-pub struct Dialogue_Helper {
-    pub text: String,
-    pub width: u32,
-    pub height: u32,
+impl DialogueBoxes {
+    pub fn get(&self, size: Size3) -> &Sprite {
+        match size {
+            Size3::Small => &self.small,
+            Size3::Medium => &self.medium,
+            Size3::Large => &self.large,
+        }
+    }
 }
\ No newline at end of file
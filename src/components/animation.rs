@@ -0,0 +1,156 @@
+use specs::prelude::*;
+use specs_derive::Component;
+use std::time::Duration;
+use super::Sprite;
+
+// Animations play by time rather than by dispatcher tick. Every frame of a clip has its own duration
+// in milliseconds, and the Animator moves each animation along by the time the last tick took, scaled
+// by the animation's speed.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LoopMode {
+    // Starts over after the last frame.
+    Loop,
+    // Stops on the last frame and counts as finished.
+    Once,
+    // Runs back and forth, showing the ends only once each time.
+    PingPong,
+}
+
+impl LoopMode {
+    pub fn from_token(token: &str) -> Option<Self> {
+        match token {
+            "loop" => Some(LoopMode::Loop),
+            "once" => Some(LoopMode::Once),
+            "pingpong" => Some(LoopMode::PingPong),
+            _ => None,
+        }
+    }
+}
+
+// Frames of an animation and how many milliseconds each is shown for.
+#[derive(Debug, Clone)]
+pub struct Clip {
+    pub frames: Vec<Sprite>,
+    pub durations: Vec<u32>,
+    pub mode: LoopMode,
+}
+
+impl Clip {
+    // Clips of different lengths can share a Playback, like the walk cycles of each direction.
+    pub fn frame(&self, index: usize) -> Sprite {
+        self.frames[index % self.frames.len()]
+    }
+}
+
+// Where an animation is up to.
+#[derive(Debug, Clone)]
+pub struct Playback {
+    pub frame: usize,
+    pub playing: bool,
+    pub finished: bool,
+    // 2.0 plays twice as fast.
+    pub speed: f32,
+    // Milliseconds the current frame has been shown for.
+    elapsed: f32,
+    backwards: bool,
+}
+
+impl Default for Playback {
+    fn default() -> Self {
+        Playback {
+            frame: 0,
+            playing: false,
+            finished: false,
+            speed: 1.0,
+            elapsed: 0.0,
+            backwards: false,
+        }
+    }
+}
+
+impl Playback {
+    pub fn play(&mut self) {
+        if !self.finished {
+            self.playing = true;
+        }
+    }
+
+    // Moves along by `ms` milliseconds. Returns true on the tick a Once clip finishes.
+    pub fn advance(&mut self, clip: &Clip, ms: f32) -> bool {
+        let count = clip.frames.len();
+        if !self.playing || count == 0 {
+            return false;
+        }
+        self.elapsed += ms * self.speed;
+        loop {
+            let frame = self.frame % count;
+            let duration = clip.durations.get(frame).copied().unwrap_or(1).max(1) as f32;
+            if self.elapsed < duration {
+                return false;
+            }
+            self.elapsed -= duration;
+
+            match clip.mode {
+                LoopMode::Loop => self.frame = (frame + 1) % count,
+                LoopMode::Once if frame + 1 < count => self.frame = frame + 1,
+                LoopMode::Once => {
+                    self.elapsed = 0.0;
+                    self.playing = false;
+                    self.finished = true;
+                    return true;
+                },
+                LoopMode::PingPong if count == 1 => {},
+                LoopMode::PingPong => {
+                    if (self.backwards && frame == 0) || (!self.backwards && frame + 1 == count) {
+                        self.backwards = !self.backwards;
+                    }
+                    self.frame = if self.backwards { frame - 1 } else { frame + 1 };
+                },
+            }
+        }
+    }
+}
+
+// The walk cycle of each direction, sharing one Playback.
+#[derive(Component, Debug, Clone)]
+#[storage(VecStorage)]
+pub struct MovementAnimation {
+    pub up: Clip,
+    pub right: Clip,
+    pub down: Clip,
+    pub left: Clip,
+    pub playback: Playback,
+}
+
+// Starts out stopped on its first frame. Interactables start theirs the first time they are used.
+#[derive(Component, Debug, Clone)]
+#[storage(VecStorage)]
+pub struct EntityAnimation {
+    pub clip: Clip,
+    pub playback: Playback,
+}
+
+impl EntityAnimation {
+    pub fn new(clip: Clip) -> Self {
+        EntityAnimation {
+            clip,
+            playback: Playback::default(),
+        }
+    }
+
+    pub fn sprite(&self) -> Sprite {
+        self.clip.frame(self.playback.frame)
+    }
+}
+
+// How long the last tick took, set by main before every dispatch.
+#[derive(Debug, Default)]
+pub struct DeltaTime(pub Duration);
+
+// Entities whose animation finished this tick. The Animator fills it, so systems that run after it
+// can react.
+#[derive(Debug, Default)]
+pub struct AnimationEvents {
+    pub finished: Vec<Entity>,
+}
@@ -0,0 +1,127 @@
+use specs::prelude::*;
+use specs_derive::Component;
+use sdl2::rect::{Point, Rect};
+use serde::{Deserialize, Serialize};
+use super::Direction;
+
+// How long something can't be hurt again after being hit, in milliseconds.
+pub const INVULNERABLE_MS: f32 = 800.0;
+// How long a knockback pushes for, in milliseconds.
+pub const KNOCKBACK_MS: f32 = 150.0;
+
+// A short-lived area in front of whoever made it, like the player's sword swing. It lasts as long
+// as the entity's animation and hurts and pushes back everything it overlaps, each thing only once.
+#[derive(Component, Debug, Clone)]
+#[storage(VecStorage)]
+pub struct Hitbox {
+    pub owner: Entity,
+    // Which way the owner was facing when it was made. Turning mid-swing doesn't move it.
+    pub direction: Direction,
+    pub rect: Rect,
+    // From the middle of the rect to the middle of the sprite, when only part of the frame hurts.
+    pub sprite_offset: Point,
+    pub damage: u32,
+    // How many pixels per tick it pushes what it hits.
+    pub knockback: i32,
+    pub hit: Vec<Entity>,
+}
+
+#[derive(Component, Debug, Clone, Serialize, Deserialize)]
+#[storage(VecStorage)]
+pub struct Health {
+    pub current: u32,
+    pub max: u32,
+    // Milliseconds left before it can be hurt again. A loaded game starts without.
+    #[serde(skip)]
+    pub invulnerable_ms: f32,
+}
+
+impl Health {
+    pub fn new(max: u32) -> Self {
+        Health {
+            current: max,
+            max,
+            invulnerable_ms: 0.0,
+        }
+    }
+
+    pub fn invulnerable(&self) -> bool {
+        self.invulnerable_ms > 0.0
+    }
+
+    pub fn dead(&self) -> bool {
+        self.current == 0
+    }
+}
+
+// A hit taken this tick. Whatever deals damage inserts one on its target, and the Vitals system
+// applies it, unless the target was hit only moments ago. Things without Health are only pushed.
+#[derive(Component, Debug, Clone)]
+#[storage(DenseVecStorage)]
+pub struct Damage {
+    pub amount: u32,
+    // Which way and how hard to push the target.
+    pub knockback: Option<(Direction, i32)>,
+}
+
+// Being pushed, which Physics does instead of walking until it runs out.
+#[derive(Component, Debug, Clone)]
+#[storage(DenseVecStorage)]
+pub struct Knockback {
+    pub direction: Direction,
+    pub speed: i32,
+    pub remaining_ms: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HostileState {
+    // Wandering about if it is an NPCWalker, otherwise standing at home.
+    Idle,
+    Chasing,
+    // Lost sight of the player and heading back home.
+    Returning,
+}
+
+// Goes after the player once they come within `sight` pixels with nothing in the way, and hits them
+// when they are within `reach` of its collision box.
+#[derive(Component, Debug, Clone)]
+#[storage(VecStorage)]
+pub struct Hostile {
+    pub home: Point,
+    pub sight: i32,
+    pub reach: i32,
+    pub damage: u32,
+    // Pixels per tick while chasing or going home.
+    pub speed: i32,
+    pub state: HostileState,
+    // Milliseconds until it can hit again.
+    pub cooldown_ms: f32,
+}
+
+// How well something fights. Damage is attack against the target's defence, and the fastest goes
+// first in battle. Hit points are kept in Health, which anything that can be hurt has.
+#[derive(Component, Debug, Clone, Default, Serialize, Deserialize)]
+#[storage(VecStorage)]
+pub struct Stats {
+    pub level: u32,
+    // Earned by winning battles. How much each level takes is in the levels table, see src/levels.rs.
+    pub experience: u32,
+    // Spent on magic in battle.
+    pub mp: u32,
+    pub max_mp: u32,
+    pub attack: u32,
+    pub defence: u32,
+    pub speed: u32,
+}
+
+// Touching this enemy starts a battle against these foes, see src/foes.rs, instead of it hitting.
+#[derive(Component, Debug, Clone)]
+#[storage(VecStorage)]
+pub struct Encounter {
+    pub foes: Vec<String>,
+}
+
+// Fights alongside the player in battles.
+#[derive(Component, Debug, Default)]
+#[storage(NullStorage)]
+pub struct PartyMember;
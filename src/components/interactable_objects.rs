@@ -10,18 +10,17 @@ pub fn interacted_with_object(obj: &Interactable) {
 }
 
 pub fn chest(_obj: &Interactable)-> std::io::Result<()> {
-    let item = "potion";
-    println!("You found a {}!", item);
+    // The chest's LootTable goes to the player's inventory, which the keyboard system hands out.
     Ok(())
 }
 
 pub fn pickup(_obj: &Interactable)-> std::io::Result<()> {
-    println!("Pickup not implemented yet!");
+    // The keyboard system moves the Pickup into the inventory and despawns it.
     Ok(())
 }
 
 pub fn destroyed_on_use(_obj: &Interactable) -> std::io::Result<()> {
-    println!("Destroyed on use not implemented yet!");
+    // The animator plays the EntityAnimation once and then deletes the entity.
     Ok(())
 }
 
@@ -31,6 +30,6 @@ pub fn character (_obj: &Interactable, thegame: &mut Gamestate, dialogue: &mut D
 }
 
 pub fn lever (_obj: &Interactable) -> std::io::Result<()> {
-    println!("Lever not implemented yet!");
+    // The keyboard system flips the Lever and sends Signals along its Links.
     Ok(())
 }
\ No newline at end of file
@@ -0,0 +1,127 @@
+use specs::prelude::*;
+use specs_derive::Component;
+use rand::{Rng, SeedableRng, distributions::Uniform, rngs::StdRng, thread_rng};
+use regex::Regex;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LootEntry {
+    pub item: String,
+    pub min: u32,
+    pub max: u32,
+    pub weight: u32,
+}
+
+impl LootEntry {
+    fn quantity<R: Rng>(&self, rng: &mut R) -> u32 {
+        rng.sample(Uniform::new_inclusive(self.min, self.max))
+    }
+}
+
+// What a chest hands out when it is first opened. Everything in `guaranteed` is always given, then
+// `rolls` picks are made from `weighted`, each entry chosen in proportion to its weight. With a seed
+// the result is the same every time, which helps when placing loot by hand.
+#[derive(Component, Debug, Clone, Default, PartialEq)]
+#[storage(VecStorage)]
+pub struct LootTable {
+    pub guaranteed: Vec<LootEntry>,
+    pub weighted: Vec<LootEntry>,
+    pub rolls: u32,
+    pub seed: Option<u64>,
+}
+
+impl LootTable {
+    // Parses a compact description, handy for map files:
+    //
+    //   "potion; apple 1-3 w2; cheese w1; rolls 2; seed 42"
+    //
+    // Entries are `item [count or min-max] [wWEIGHT]`. Entries with a weight go into the random pool,
+    // the rest are guaranteed. `rolls` defaults to 1 if there is a pool, and needs one to be given.
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let entry_pattern = Regex::new(r"^(\w+)(?: (\d+)(?:-(\d+))?)?(?: w(\d+))?$").unwrap();
+        let setting_pattern = Regex::new(r"^(rolls|seed) (\d+)$").unwrap();
+
+        let mut table = LootTable::default();
+        let mut rolls = None;
+        for part in source.split(';').map(str::trim).filter(|p| !p.is_empty()) {
+            if let Some(cap) = setting_pattern.captures(part) {
+                let too_large = |e| format!("\"{}\": {}", part, e);
+                match &cap[1] {
+                    "rolls" => rolls = Some(cap[2].parse::<u32>().map_err(too_large)?),
+                    _ => table.seed = Some(cap[2].parse::<u64>().map_err(too_large)?),
+                }
+            } else if let Some(cap) = entry_pattern.captures(part) {
+                let number = |i: usize| match cap.get(i) {
+                    Some(m) => m.as_str().parse::<u32>().map(Some).map_err(|e| format!("\"{}\": {}", part, e)),
+                    None => Ok(None),
+                };
+                let min = number(2)?.unwrap_or(1);
+                let max = number(3)?.unwrap_or(min);
+                if max < min {
+                    return Err(format!("\"{}\": the range {}-{} is backwards", part, min, max));
+                }
+                let entry = LootEntry {
+                    item: cap[1].to_string(),
+                    min,
+                    max,
+                    weight: number(4)?.unwrap_or(0),
+                };
+                if cap.get(4).is_some() {
+                    if entry.weight == 0 {
+                        return Err(format!("\"{}\": weights must be above zero", part));
+                    }
+                    table.weighted.push(entry);
+                } else {
+                    table.guaranteed.push(entry);
+                }
+            } else {
+                return Err(format!("could not parse loot \"{}\"", part));
+            }
+        }
+        table.rolls = match rolls {
+            Some(_) if table.weighted.is_empty() => return Err("rolls are only made from entries with a weight".to_string()),
+            Some(rolls) => rolls,
+            None if table.weighted.is_empty() => 0,
+            None => 1,
+        };
+        Ok(table)
+    }
+
+    pub fn roll(&self) -> Vec<(String, u32)> {
+        match self.seed {
+            Some(seed) => self.roll_with(&mut StdRng::seed_from_u64(seed)),
+            None => self.roll_with(&mut thread_rng()),
+        }
+    }
+
+    fn roll_with<R: Rng>(&self, rng: &mut R) -> Vec<(String, u32)> {
+        let mut loot: Vec<(String, u32)> = Vec::new();
+        let mut add = |item: &str, count: u32| {
+            match loot.iter_mut().find(|(i, _)| i == item) {
+                Some((_, c)) => *c = c.saturating_add(count),
+                None => loot.push((item.to_string(), count)),
+            }
+        };
+
+        for entry in &self.guaranteed {
+            add(&entry.item, entry.quantity(rng));
+        }
+
+        // Summed as u64, so plenty of large weights don't overflow.
+        let total_weight: u64 = self.weighted.iter().map(|e| e.weight as u64).sum();
+        if total_weight > 0 {
+            for _ in 0..self.rolls {
+                let mut pick = rng.gen_range(0, total_weight);
+                for entry in &self.weighted {
+                    if pick < entry.weight as u64 {
+                        add(&entry.item, entry.quantity(rng));
+                        break;
+                    }
+                    pick -= entry.weight as u64;
+                }
+            }
+        }
+
+        loot.retain(|(_, count)| *count > 0);
+        loot
+    }
+}
@@ -0,0 +1,136 @@
+use specs::prelude::*;
+use specs_derive::Component;
+use std::collections::VecDeque;
+use serde::{Deserialize, Serialize};
+use super::{CollisionBox, Sprite};
+
+// How entities affect each other: a source (lever, pressure plate, chest) has Links naming its targets,
+// and whenever it is used or changes state it queues a Signal for each of them. The Switches system
+// then hands the signals to every entity carrying that Name. Several entities may share a name, so
+// one lever can open both halves of a gate.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Signal {
+    Activate,
+    Deactivate,
+    Toggle,
+}
+
+impl Signal {
+    pub fn from_token(token: &str) -> Option<Self> {
+        match token {
+            "activate" => Some(Signal::Activate),
+            "deactivate" => Some(Signal::Deactivate),
+            "toggle" => Some(Signal::Toggle),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Component, Debug, Clone, PartialEq)]
+#[storage(VecStorage)]
+pub struct Name(pub String);
+
+// `signal` overrides what is sent. Without it targets follow the source: Activate when it turns on,
+// Deactivate when it turns off.
+#[derive(Component, Debug, Clone, Default)]
+#[storage(VecStorage)]
+pub struct Links {
+    pub targets: Vec<String>,
+    pub signal: Option<Signal>,
+}
+
+#[derive(Debug, Default)]
+pub struct Signals {
+    pub queue: VecDeque<(String, Signal)>,
+}
+
+impl Signals {
+    pub fn send(&mut self, links: &Links, on: bool) {
+        let signal = match (links.signal, on) {
+            (Some(signal), _) => signal,
+            (None, true) => Signal::Activate,
+            (None, false) => Signal::Deactivate,
+        };
+        for target in &links.targets {
+            self.queue.push_back((target.clone(), signal));
+        }
+    }
+}
+
+fn receive(on: bool, signal: Signal) -> bool {
+    match signal {
+        Signal::Activate => true,
+        Signal::Deactivate => false,
+        Signal::Toggle => !on,
+    }
+}
+
+// Something with an on and an off look, like a lamp.
+#[derive(Component, Debug, Clone)]
+#[storage(VecStorage)]
+pub struct Switch {
+    pub on: bool,
+    pub off_sprite: Sprite,
+    pub on_sprite: Sprite,
+}
+
+impl Switch {
+    pub fn sprite(&self) -> Sprite {
+        if self.on { self.on_sprite } else { self.off_sprite }
+    }
+
+    pub fn receive(&mut self, signal: Signal) {
+        self.on = receive(self.on, signal);
+    }
+}
+
+// Flips between on and off each time it is used. If `variable` is set, the state is also kept in the
+// world variables so dialogue can check it. A lever can be a target too, which moves it without
+// passing the signal on.
+#[derive(Component, Debug, Clone, Default, Serialize, Deserialize)]
+#[storage(VecStorage)]
+pub struct Lever {
+    pub on: bool,
+    pub variable: Option<String>,
+}
+
+impl Lever {
+    pub fn receive(&mut self, signal: Signal) {
+        self.on = receive(self.on, signal);
+    }
+}
+
+// Sends Activate while something is standing on it, and Deactivate once it is left.
+#[derive(Component, Debug, Clone, Default)]
+#[storage(VecStorage)]
+pub struct PressurePlate {
+    pub pressed: bool,
+}
+
+// Blocks the way while closed. An open door has no CollisionBox, the closed one is kept here.
+#[derive(Component, Debug, Clone)]
+#[storage(VecStorage)]
+pub struct Door {
+    pub open: bool,
+    pub closed_sprite: Sprite,
+    pub open_sprite: Sprite,
+    pub collision: CollisionBox,
+}
+
+impl Door {
+    pub fn sprite(&self) -> Sprite {
+        if self.open { self.open_sprite } else { self.closed_sprite }
+    }
+
+    pub fn receive(&mut self, signal: Signal) {
+        self.open = receive(self.open, signal);
+    }
+}
+
+// Drops an item at its position each time it is activated or toggled.
+#[derive(Component, Debug, Clone)]
+#[storage(VecStorage)]
+pub struct ItemSpawner {
+    pub item: String,
+}
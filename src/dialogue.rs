@@ -0,0 +1,382 @@
+use crate::components::*;
+use crate::textures::TextureRegistry;
+use crate::variables::{Condition, Effect, WorldVariables};
+use std::{collections::{HashMap, VecDeque}, fs};
+use regex::Regex;
+use sdl2::rect::Rect;
+
+// Dialogue file format:
+//
+// [node_name]                  Starts a new node. The first node in the file is where the conversation starts.
+// : "Speaker", small           A spoken line. The text is on the following line. The size picks the dialogue
+//                              box (small, medium or large); `auto` uses the smallest box the text fits in.
+// * "Choice text" -> target    A player choice, jumping to node `target` when picked.
+// -> target                    Jump to `target` once all lines in the node have been shown.
+// ! met_reaper = true          Set a world variable when the node is entered. `+=` and `-=` work on integers.
+//
+// Spoken text is revealed a character at a time. `{pause}` in the text holds the reveal for half a second,
+// `{pause 1200}` for the given number of milliseconds.
+//
+// Spoken lines, choices and jumps can end in a condition, `? chests_opened >= 1 && !met_reaper`, and are
+// skipped unless it holds. A node may have several jumps; the first one whose condition holds is taken.
+// Line and choice conditions are checked when the node is entered, before its effects are applied.
+// Jump conditions are checked when the node is left.
+//
+// `END` as a target ends the conversation, as does reaching the end of a node without a jump.
+// Lines before the first node header go into an implicit node called `start`.
+
+pub const DIALOGUE_END: &str = "END";
+const DEFAULT_START_NODE: &str = "start";
+const DEFAULT_PAUSE_MS: u32 = 500;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DialogueJump {
+    Node(String),
+    End,
+}
+
+impl DialogueJump {
+    fn from_target(target: &str) -> Self {
+        if target == DIALOGUE_END {
+            DialogueJump::End
+        } else {
+            DialogueJump::Node(target.to_string())
+        }
+    }
+}
+
+fn holds(condition: &Option<Condition>, variables: &WorldVariables) -> bool {
+    condition.as_ref().is_none_or(|c| c.holds(variables))
+}
+
+fn parse_condition(condition: Option<regex::Match>, line_no: usize) -> Result<Option<Condition>, String> {
+    match condition {
+        Some(c) => Condition::parse(c.as_str())
+            .map(Some)
+            .map_err(|e| format!("line {}: {}", line_no, e)),
+        None => Ok(None),
+    }
+}
+
+// Strips pause markers out of spoken text, noting after how many characters each one occurs.
+// Whitespace is collapsed so the text can be wrapped and put back together character for character.
+fn parse_text(raw: &str) -> Result<(String, Vec<(usize, u32)>), String> {
+    let pause_pattern = Regex::new(r"\{pause(?: (\d+))?\}").unwrap();
+    let raw = raw.split_whitespace().collect::<Vec<_>>().join(" ");
+
+    let mut text = String::new();
+    let mut pauses = Vec::new();
+    let mut last = 0;
+    for cap in pause_pattern.captures_iter(&raw) {
+        let marker = cap.get(0).unwrap();
+        push_text(&mut text, &raw[last..marker.start()]);
+        let ms = match cap.get(1) {
+            Some(ms) => ms.as_str().parse::<u32>().map_err(|e| e.to_string())?,
+            None => DEFAULT_PAUSE_MS,
+        };
+        pauses.push((text.chars().count(), ms));
+        last = marker.end();
+    }
+    push_text(&mut text, &raw[last..]);
+
+    let text = text.trim_end().to_string();
+    let length = text.chars().count();
+    for pause in &mut pauses {
+        pause.0 = pause.0.min(length);
+    }
+    Ok((text, pauses))
+}
+
+// Avoids a double space where a marker stood between two words.
+fn push_text(text: &mut String, segment: &str) {
+    if text.is_empty() || text.ends_with(' ') {
+        text.push_str(segment.trim_start());
+    } else {
+        text.push_str(segment);
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DialogueLine {
+    pub item: Dialogue_Single_item,
+    pub condition: Option<Condition>,
+}
+
+#[derive(Debug, Clone)]
+pub struct DialogueChoice {
+    pub text: String,
+    pub target: DialogueJump,
+    pub condition: Option<Condition>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ConditionalJump {
+    pub target: DialogueJump,
+    pub condition: Option<Condition>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DialogueNode {
+    pub lines: Vec<DialogueLine>,
+    pub choices: Vec<DialogueChoice>,
+    pub jumps: Vec<ConditionalJump>,
+    pub effects: Vec<Effect>,
+}
+
+impl DialogueNode {
+    fn next(&self, variables: &WorldVariables) -> DialogueJump {
+        self.jumps.iter()
+            .find(|jump| holds(&jump.condition, variables))
+            .map_or(DialogueJump::End, |jump| jump.target.clone())
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DialogueTree {
+    pub start: String,
+    pub nodes: HashMap<String, DialogueNode>,
+}
+
+impl DialogueTree {
+    pub fn load(path: &str) -> Result<Self, String> {
+        let conv = fs::read_to_string(path).map_err(|e| format!("Could not read dialogue file {}: {}", path, e))?;
+        Self::parse(&conv).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let node_pattern = Regex::new(r"^\[(\w+)\]$").unwrap();
+        let speaker_pattern = Regex::new(r#"^: "(.+)", (\w+)(?: \? (.+))?$"#).unwrap();
+        let choice_pattern = Regex::new(r#"^\* "(.+)" -> (\w+)(?: \? (.+))?$"#).unwrap();
+        let jump_pattern = Regex::new(r"^-> (\w+)(?: \? (.+))?$").unwrap();
+        let effect_pattern = Regex::new(r"^! (.+)$").unwrap();
+
+        let mut tree = DialogueTree::default();
+        let mut current: Option<String> = None;
+        // A speaker tag waiting for its text, with the line it is on.
+        let mut speaker: Option<(usize, String, Option<Size3>, Option<Condition>)> = None;
+
+        for (line_no, line) in source.lines().enumerate().map(|(i, l)| (i + 1, l.trim())) {
+            if line.is_empty() || line.starts_with("//") {
+                continue;
+            }
+
+            if let Some((_, name, background_size, condition)) = speaker.take() {
+                // The line after a speaker tag is always the spoken text.
+                let (dialogue_text, pauses) = parse_text(line).map_err(|e| format!("line {}: {}", line_no, e))?;
+                let node = tree.node_mut(&mut current);
+                node.lines.push(DialogueLine {
+                    item: Dialogue_Single_item {
+                        speaker_name: name,
+                        dialogue_text,
+                        background_size,
+                        pauses,
+                    },
+                    condition,
+                });
+            } else if let Some(cap) = node_pattern.captures(line) {
+                let name = cap[1].to_string();
+                if tree.nodes.contains_key(&name) {
+                    return Err(format!("line {}: node [{}] is defined twice", line_no, name));
+                }
+                tree.nodes.insert(name.clone(), DialogueNode::default());
+                if tree.start.is_empty() {
+                    tree.start = name.clone();
+                }
+                current = Some(name);
+            } else if let Some(cap) = speaker_pattern.captures(line) {
+                let size = match &cap[2] {
+                    "auto" => None,
+                    token => Some(Size3::from_token(token)
+                        .ok_or_else(|| format!("line {}: unknown dialogue box size \"{}\"", line_no, token))?),
+                };
+                speaker = Some((line_no, cap[1].to_string(), size, parse_condition(cap.get(3), line_no)?));
+            } else if let Some(cap) = choice_pattern.captures(line) {
+                let condition = parse_condition(cap.get(3), line_no)?;
+                tree.node_mut(&mut current).choices.push(DialogueChoice {
+                    text: cap[1].to_string(),
+                    target: DialogueJump::from_target(&cap[2]),
+                    condition,
+                });
+            } else if let Some(cap) = jump_pattern.captures(line) {
+                let condition = parse_condition(cap.get(2), line_no)?;
+                tree.node_mut(&mut current).jumps.push(ConditionalJump {
+                    target: DialogueJump::from_target(&cap[1]),
+                    condition,
+                });
+            } else if let Some(cap) = effect_pattern.captures(line) {
+                let effect = Effect::parse(&cap[1]).map_err(|e| format!("line {}: {}", line_no, e))?;
+                tree.node_mut(&mut current).effects.push(effect);
+            } else {
+                return Err(format!("line {}: could not parse \"{}\"", line_no, line));
+            }
+        }
+
+        if let Some((line_no, ..)) = speaker {
+            return Err(format!("line {}: file ends with a speaker tag but no text", line_no));
+        }
+        tree.validate()?;
+        Ok(tree)
+    }
+
+    fn node_mut(&mut self, current: &mut Option<String>) -> &mut DialogueNode {
+        let name = current.get_or_insert_with(|| DEFAULT_START_NODE.to_string()).clone();
+        if self.start.is_empty() {
+            self.start = name.clone();
+        }
+        self.nodes.entry(name).or_default()
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.nodes.is_empty() {
+            return Err("no dialogue found".to_string());
+        }
+        for (name, node) in &self.nodes {
+            let targets = node.choices.iter().map(|c| &c.target).chain(node.jumps.iter().map(|j| &j.target));
+            for target in targets {
+                if let DialogueJump::Node(target) = target {
+                    if !self.nodes.contains_key(target) {
+                        return Err(format!("node [{}] jumps to unknown node [{}]", name, target));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// How each speaker is presented in the dialogue box. Loaded from a file with one speaker per line:
+//
+// "Reaper": reaper 0 0 32 36   Name, then the spritesheet and region (x y width height) of the portrait.
+// "Narrator"                   Speakers without a portrait only get a name plate.
+#[derive(Debug, Default)]
+pub struct Speakers {
+    portraits: HashMap<String, Sprite>,
+}
+
+impl Speakers {
+    pub fn load(path: &str, textures: &TextureRegistry) -> Result<Self, String> {
+        let source = fs::read_to_string(path).map_err(|e| format!("Could not read speaker file {}: {}", path, e))?;
+
+        let speaker_pattern = Regex::new(r#"^"(.+)"(?:: (\w+) (\d+) (\d+) (\d+) (\d+))?$"#).unwrap();
+        let mut speakers = Speakers::default();
+        for (line_no, line) in source.lines().enumerate().map(|(i, l)| (i + 1, l.trim())) {
+            if line.is_empty() || line.starts_with("//") {
+                continue;
+            }
+            let cap = speaker_pattern.captures(line)
+                .ok_or_else(|| format!("{}: line {}: could not parse \"{}\"", path, line_no, line))?;
+            if let Some(sheet) = cap.get(2) {
+                let spritesheet = textures.get(sheet.as_str())
+                    .ok_or_else(|| format!("{}: line {}: unknown spritesheet \"{}\"", path, line_no, sheet.as_str()))?;
+                // The pattern only matches digits, so these can't fail.
+                let n = |i: usize| cap[i].parse::<i32>().unwrap();
+                speakers.portraits.insert(cap[1].to_string(), Sprite {
+                    spritesheet,
+                    region: Rect::new(n(3), n(4), n(5) as u32, n(6) as u32),
+                });
+            }
+        }
+        Ok(speakers)
+    }
+
+    pub fn portrait(&self, speaker: &str) -> Option<&Sprite> {
+        self.portraits.get(speaker)
+    }
+}
+
+// The conversation currently being played, stored as a resource while in Gamestate::Dialogue.
+#[derive(Debug, Default)]
+pub struct ActiveDialogue {
+    pub tree: DialogueTree,
+    pub lines: VecDeque<Dialogue_Single_item>,
+    pub choices: Vec<DialogueChoice>,
+    pub selected: usize,
+    node: Option<String>,
+}
+
+impl ActiveDialogue {
+    // Returns false if the conversation ended before showing anything.
+    pub fn start(&mut self, tree: DialogueTree, variables: &mut WorldVariables) -> bool {
+        let start = DialogueJump::Node(tree.start.clone());
+        self.tree = tree;
+        self.goto(start, variables)
+    }
+
+    pub fn current_line(&self) -> Option<&Dialogue_Single_item> {
+        self.lines.front()
+    }
+
+    // Choices are offered together with the last line of a node.
+    pub fn awaiting_choice(&self) -> bool {
+        !self.choices.is_empty() && self.lines.len() <= 1
+    }
+
+    pub fn select_previous(&mut self) {
+        if self.awaiting_choice() && self.selected > 0 {
+            self.selected -= 1;
+        }
+    }
+
+    pub fn select_next(&mut self) {
+        if self.awaiting_choice() && self.selected + 1 < self.choices.len() {
+            self.selected += 1;
+        }
+    }
+
+    // Moves the conversation forward. Returns false once it has ended.
+    pub fn advance(&mut self, variables: &mut WorldVariables) -> bool {
+        if self.awaiting_choice() {
+            let target = self.choices[self.selected].target.clone();
+            return self.goto(target, variables);
+        }
+        self.lines.pop_front();
+        if self.lines.is_empty() {
+            let next = match self.node.take().and_then(|name| self.tree.nodes.get(&name)) {
+                Some(node) => node.next(variables),
+                None => DialogueJump::End,
+            };
+            return self.goto(next, variables);
+        }
+        true
+    }
+
+    fn goto(&mut self, mut target: DialogueJump, variables: &mut WorldVariables) -> bool {
+        // Nodes with nothing to show just pass through, so guard against jumping in circles.
+        for _ in 0..=self.tree.nodes.len() {
+            let (name, node) = match &target {
+                DialogueJump::End => break,
+                DialogueJump::Node(name) => match self.tree.nodes.get(name) {
+                    Some(node) => (name.clone(), node),
+                    None => break,
+                },
+            };
+
+            let lines: VecDeque<Dialogue_Single_item> = node.lines.iter()
+                .filter(|line| holds(&line.condition, variables))
+                .map(|line| line.item.clone())
+                .collect();
+            let choices: Vec<DialogueChoice> = node.choices.iter()
+                .filter(|choice| holds(&choice.condition, variables))
+                .cloned()
+                .collect();
+            for effect in &node.effects {
+                effect.apply(variables);
+            }
+
+            if lines.is_empty() && choices.is_empty() {
+                target = node.next(variables);
+                continue;
+            }
+            self.lines = lines;
+            self.choices = choices;
+            self.selected = 0;
+            self.node = Some(name);
+            return true;
+        }
+        self.lines.clear();
+        self.choices.clear();
+        self.selected = 0;
+        self.node = None;
+        false
+    }
+}
@@ -0,0 +1,112 @@
+use crate::animations::SpriteSheets;
+use crate::components::*;
+use std::{collections::HashMap, fs};
+use regex::Regex;
+
+// Foe definitions file format, one block per kind of foe met in battle:
+//
+// [slime]                          Foe id, which enemies name in their `encounter` property.
+// name: Slime
+// sprite: reaper walk_down         Sprite sheet and clip it is shown with, see src/animations.rs.
+// health: 6
+// attack: 3                        Attack, defence and speed work like the party's, see Stats.
+// defence: 1
+// speed: 2
+// experience: 4                    Given to everyone in the party when it is beaten.
+// loot: apple w1                   Optional. What it leaves behind, see LootTable::parse.
+
+#[derive(Debug, Clone)]
+pub struct FoeDefinition {
+    pub name: String,
+    pub sprite: Sprite,
+    pub health: u32,
+    pub stats: Stats,
+    pub experience: u32,
+    pub loot: LootTable,
+}
+
+#[derive(Debug, Default)]
+pub struct FoeDatabase {
+    foes: HashMap<String, FoeDefinition>,
+}
+
+impl FoeDatabase {
+    pub fn load(path: &str, sheets: &SpriteSheets) -> Result<Self, String> {
+        let source = fs::read_to_string(path).map_err(|e| format!("Could not read foe file {}: {}", path, e))?;
+        Self::parse(&source, sheets).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    fn parse(source: &str, sheets: &SpriteSheets) -> Result<Self, String> {
+        let header_pattern = Regex::new(r"^\[(\w+)\]$").unwrap();
+        let field_pattern = Regex::new(r"^(\w+): *(.*)$").unwrap();
+
+        let mut database = FoeDatabase::default();
+        let mut current: Option<(String, HashMap<String, String>)> = None;
+
+        for (line_no, line) in source.lines().enumerate().map(|(i, l)| (i + 1, l.trim())) {
+            if line.is_empty() || line.starts_with("//") {
+                continue;
+            }
+            if let Some(cap) = header_pattern.captures(line) {
+                if let Some((id, fields)) = current.take() {
+                    database.insert(id, fields, sheets)?;
+                }
+                if database.foes.contains_key(&cap[1]) {
+                    return Err(format!("line {}: foe [{}] is defined twice", line_no, &cap[1]));
+                }
+                current = Some((cap[1].to_string(), HashMap::new()));
+            } else if let Some(cap) = field_pattern.captures(line) {
+                match &mut current {
+                    Some((_, fields)) => { fields.insert(cap[1].to_string(), cap[2].to_string()); },
+                    None => return Err(format!("line {}: field outside of a foe block", line_no)),
+                }
+            } else {
+                return Err(format!("line {}: could not parse \"{}\"", line_no, line));
+            }
+        }
+        if let Some((id, fields)) = current.take() {
+            database.insert(id, fields, sheets)?;
+        }
+        Ok(database)
+    }
+
+    fn insert(&mut self, id: String, mut fields: HashMap<String, String>, sheets: &SpriteSheets) -> Result<(), String> {
+        let mut field = |name: &str| fields.remove(name)
+            .ok_or_else(|| format!("foe [{}] has no {}", id, name));
+        let number = |name: &str, value: String| value.parse::<u32>()
+            .map_err(|_| format!("foe [{}]: {} should be a number, not \"{}\"", id, name, value));
+
+        let sprite = field("sprite")?;
+        let sprite = match sprite.split_whitespace().collect::<Vec<_>>().as_slice() {
+            [sheet, clip] => sheets.sprite(sheet, clip).map_err(|e| format!("foe [{}]: {}", id, e))?,
+            _ => return Err(format!("foe [{}]: sprite should be a sheet and a clip, not \"{}\"", id, sprite)),
+        };
+        let loot = match field("loot") {
+            Ok(loot) => LootTable::parse(&loot).map_err(|e| format!("foe [{}]: {}", id, e))?,
+            Err(_) => LootTable::default(),
+        };
+
+        let foe = FoeDefinition {
+            name: field("name")?,
+            sprite,
+            health: number("health", field("health")?)?.max(1),
+            stats: Stats {
+                attack: number("attack", field("attack")?)?,
+                defence: number("defence", field("defence")?)?,
+                speed: number("speed", field("speed")?)?,
+                ..Stats::default()
+            },
+            experience: number("experience", field("experience")?)?,
+            loot,
+        };
+        if let Some(unknown) = fields.keys().next() {
+            return Err(format!("foe [{}] has unknown field \"{}\"", id, unknown));
+        }
+        self.foes.insert(id, foe);
+        Ok(())
+    }
+
+    pub fn get(&self, id: &str) -> Option<&FoeDefinition> {
+        self.foes.get(id)
+    }
+}
@@ -0,0 +1,149 @@
+use sdl2::pixels::Color;
+use sdl2::render::{Texture, TextureCreator};
+use sdl2::ttf::{Font, FontStyle, Sdl2TtfContext};
+use sdl2::video::WindowContext;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+// Rendered text is thrown away once it has gone this many frames without being drawn.
+const CACHE_FRAMES: u64 = 120;
+
+#[derive(Debug, Clone, Copy)]
+pub struct TextStyle {
+    pub font: &'static str,
+    pub size: u16,
+    pub style: FontStyle,
+    pub color: Color,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct FontKey {
+    font: &'static str,
+    size: u16,
+    style: FontStyle,
+}
+
+impl From<&TextStyle> for FontKey {
+    fn from(style: &TextStyle) -> Self {
+        FontKey {
+            font: style.font,
+            size: style.size,
+            style: style.style,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct TextKey {
+    font: FontKey,
+    color: (u8, u8, u8, u8),
+    text: String,
+}
+
+struct CachedText<'tex> {
+    texture: Texture<'tex>,
+    last_used: u64,
+}
+
+// Loads each font once, at each size and style asked for, and keeps the textures of recently drawn text
+// around so they aren't rendered again every frame. A font that can't be loaded is reported once, after
+// which text using it is simply not drawn.
+pub struct FontManager<'ttf, 'tex> {
+    ttf_context: &'ttf Sdl2TtfContext,
+    texture_creator: &'tex TextureCreator<WindowContext>,
+    paths: HashMap<&'static str, PathBuf>,
+    fonts: HashMap<FontKey, Option<Font<'ttf, 'static>>>,
+    cache: HashMap<TextKey, CachedText<'tex>>,
+    frame: u64,
+}
+
+impl<'ttf, 'tex> FontManager<'ttf, 'tex> {
+    pub fn new(ttf_context: &'ttf Sdl2TtfContext, texture_creator: &'tex TextureCreator<WindowContext>) -> Self {
+        FontManager {
+            ttf_context,
+            texture_creator,
+            paths: HashMap::new(),
+            fonts: HashMap::new(),
+            cache: HashMap::new(),
+            frame: 0,
+        }
+    }
+
+    pub fn register<P: AsRef<Path>>(&mut self, name: &'static str, path: P) {
+        let path = path.as_ref();
+        if !path.exists() {
+            println!("Font \"{}\" not found at {}, text using it will not be shown.", name, path.display());
+        }
+        self.paths.insert(name, path.to_path_buf());
+    }
+
+    fn font(&mut self, style: &TextStyle) -> Option<&Font<'ttf, 'static>> {
+        let key = FontKey::from(style);
+        if !self.fonts.contains_key(&key) {
+            let font = match self.paths.get(style.font) {
+                Some(path) => match self.ttf_context.load_font(path, style.size) {
+                    Ok(mut font) => {
+                        font.set_style(style.style);
+                        Some(font)
+                    },
+                    Err(e) => {
+                        println!("Could not load font \"{}\" from {}: {}", style.font, path.display(), e);
+                        None
+                    },
+                },
+                None => {
+                    println!("No font registered as \"{}\".", style.font);
+                    None
+                },
+            };
+            self.fonts.insert(key.clone(), font);
+        }
+        self.fonts[&key].as_ref()
+    }
+
+    pub fn size_of(&mut self, style: &TextStyle, text: &str) -> Option<(u32, u32)> {
+        self.font(style)?.size_of(text).ok()
+    }
+
+    pub fn line_spacing(&mut self, style: &TextStyle) -> i32 {
+        match self.font(style) {
+            Some(font) => font.recommended_line_spacing(),
+            None => style.size as i32,
+        }
+    }
+
+    pub fn text(&mut self, style: &TextStyle, text: &str) -> Option<&Texture<'tex>> {
+        if text.is_empty() {
+            // SDL_ttf refuses to render empty text
+            return None;
+        }
+        let key = TextKey {
+            font: FontKey::from(style),
+            color: style.color.rgba(),
+            text: text.to_string(),
+        };
+        if !self.cache.contains_key(&key) {
+            let texture_creator = self.texture_creator;
+            let surface = self.font(style)?
+                .render(text)
+                .blended(style.color)
+                .map_err(|e| println!("Could not render \"{}\": {}", text, e))
+                .ok()?;
+            let texture = texture_creator.create_texture_from_surface(&surface)
+                .map_err(|e| println!("Could not render \"{}\": {}", text, e))
+                .ok()?;
+            self.cache.insert(key.clone(), CachedText { texture, last_used: self.frame });
+        }
+        let frame = self.frame;
+        let cached = self.cache.get_mut(&key)?;
+        cached.last_used = frame;
+        Some(&cached.texture)
+    }
+
+    // Call once per frame, after drawing.
+    pub fn end_frame(&mut self) {
+        let frame = self.frame;
+        self.cache.retain(|_, cached| frame - cached.last_used < CACHE_FRAMES);
+        self.frame += 1;
+    }
+}
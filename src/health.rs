@@ -0,0 +1,115 @@
+use specs::prelude::*;
+use crate::components::*;
+use crate::items::{ItemDatabase, drop_items};
+use crate::level;
+use super::Gamestate;
+
+pub struct Vitals;
+
+impl<'a> System<'a> for Vitals {
+
+    type SystemData = (
+        Entities<'a>,
+        ReadExpect<'a, DeltaTime>,
+        WriteExpect<'a, Gamestate>,
+        WriteExpect<'a, Notifications>,
+        ReadExpect<'a, ItemDatabase>,
+        Read<'a, LazyUpdate>,
+        WriteStorage<'a, Health>,
+        WriteStorage<'a, Damage>,
+        WriteStorage<'a, Knockback>,
+        ReadStorage<'a, Playable>,
+        ReadStorage<'a, Position>,
+        ReadStorage<'a, LootTable>,
+    );
+
+    fn run(&mut self, (
+        entities,
+        delta,
+        mut gamestate,
+        mut notifications,
+        item_database,
+        lazy,
+        mut health,
+        mut damage,
+        mut knockback,
+        playable,
+        position,
+        loot,
+    ): Self::SystemData) {
+        let ms = delta.0.as_secs_f32() * 1000.0;
+
+        let mut stopped = Vec::new();
+        for (entity, knock) in (&entities, &mut knockback).join() {
+            knock.remaining_ms -= ms;
+            if knock.remaining_ms <= 0.0 {
+                stopped.push(entity);
+            }
+        }
+        for entity in stopped {
+            knockback.remove(entity);
+        }
+        for hp in (&mut health).join() {
+            hp.invulnerable_ms = (hp.invulnerable_ms - ms).max(0.0);
+        }
+
+        for (entity, hit) in (&entities, damage.drain()).join() {
+            if let Some(hp) = health.get_mut(entity) {
+                // Just hit, or already gone.
+                if hp.invulnerable() || hp.dead() {
+                    continue;
+                }
+                hp.current = hp.current.saturating_sub(hit.amount);
+                hp.invulnerable_ms = INVULNERABLE_MS;
+            }
+            if let Some((direction, speed)) = hit.knockback {
+                knockback.insert(entity, Knockback { direction, speed, remaining_ms: KNOCKBACK_MS }).ok();
+            }
+        }
+
+        for (entity, hp, pos, loot) in (&entities, &health, &position, (&loot).maybe()).join() {
+            if !hp.dead() {
+                continue;
+            }
+            if playable.contains(entity) {
+                if *gamestate != Gamestate::GameOver {
+                    *gamestate = Gamestate::GameOver;
+                    notifications.push("You have fallen.".to_string());
+                }
+                continue;
+            }
+
+            // Whatever it carried is left behind.
+            let found = loot.map(LootTable::roll).unwrap_or_default();
+            drop_items(&lazy, &entities, &item_database, pos.0, &found);
+            entities.delete(entity).ok();
+        }
+    }
+}
+
+// Back on your feet at the start of the level, after a game over with nothing saved to go back to.
+pub fn revive(world: &mut World) {
+    level::place_player(world);
+    let entities: Vec<Entity> = (&world.entities(), &world.read_storage::<Playable>()).join()
+        .map(|(entity, _)| entity)
+        .collect();
+    for entity in entities {
+        if let Some(hp) = world.write_storage::<Health>().get_mut(entity) {
+            hp.current = hp.max;
+            hp.invulnerable_ms = INVULNERABLE_MS;
+        }
+        if let Some(stats) = world.write_storage::<Stats>().get_mut(entity) {
+            stats.mp = stats.max_mp;
+        }
+        let position = world.read_storage::<Position>().get(entity).copied();
+        if let (Some(flag), Some(position)) = (world.write_storage::<FlagForMovement>().get_mut(entity), position) {
+            flag.moving = false;
+            flag.new_pos = position;
+        }
+        if let Some(vel) = world.write_storage::<Velocity>().get_mut(entity) {
+            vel.direction.clear();
+        }
+        world.write_storage::<Knockback>().remove(entity);
+    }
+    *world.write_resource() = Gamestate::Running;
+}
@@ -0,0 +1,142 @@
+use specs::{System, ReadStorage, WriteStorage, ReadExpect, WriteExpect, join::Join, Entities, Entity};
+use sdl2::rect::{Rect, Point};
+use crate::components::*;
+use crate::tilemap::TileMap;
+use crate::battle::Battle;
+use super::Gamestate;
+
+// Once chasing, the player has to get this much further away than the sight radius to shake it off.
+const LOSE_SIGHT_FACTOR: i32 = 2;
+const ATTACK_COOLDOWN_MS: f32 = 1000.0;
+// Pixels per tick, for KNOCKBACK_MS.
+const ATTACK_KNOCKBACK: i32 = 6;
+// RandomWalker picks where idle walkers go, this is just how fast.
+const WANDER_SPEED: i32 = 1;
+
+pub struct Hostiles;
+
+// Along whichever axis is further off, or the other one if that way was blocked last tick.
+fn toward(from: Point, to: Point, blocked: bool) -> Direction {
+    let (dx, dy) = (to.x() - from.x(), to.y() - from.y());
+    if (dx.abs() > dy.abs()) != blocked {
+        if dx < 0 { Direction::Left } else { Direction::Right }
+    } else if dy < 0 {
+        Direction::Up
+    } else {
+        Direction::Down
+    }
+}
+
+fn walk(vel: &mut Velocity, direction: Option<Direction>, speed: i32) {
+    vel.direction.clear();
+    vel.direction.extend(direction);
+    vel.speed = speed;
+}
+
+impl<'a> System<'a> for Hostiles {
+
+    type SystemData = (
+        Entities<'a>,
+        ReadExpect<'a, DeltaTime>,
+        ReadExpect<'a, Gamestate>,
+        ReadExpect<'a, TileMap>,
+        ReadStorage<'a, KeyboardControlled>,
+        ReadStorage<'a, Position>,
+        ReadStorage<'a, CollisionBox>,
+        ReadStorage<'a, Health>,
+        ReadStorage<'a, StaticCollider>,
+        ReadStorage<'a, NPCWalker>,
+        ReadStorage<'a, FlagForMovement>,
+        WriteStorage<'a, Hostile>,
+        WriteStorage<'a, Velocity>,
+        WriteStorage<'a, Damage>,
+        ReadStorage<'a, Encounter>,
+        WriteExpect<'a, Battle>,
+    );
+
+    fn run(&mut self, (
+        entities,
+        delta,
+        gamestate,
+        tilemap,
+        keyboard_controlled,
+        position,
+        collisionbox,
+        health,
+        static_collider,
+        walker,
+        movementflags,
+        mut hostile,
+        mut velocity,
+        mut damage,
+        encounter,
+        mut battle,
+    ): Self::SystemData) {
+        // Nobody sneaks up on the player while they read or look through their bag.
+        if *gamestate != Gamestate::Running {
+            for (_, vel) in (&hostile, &mut velocity).join() {
+                walk(vel, None, 0);
+            }
+            return;
+        }
+        let ms = delta.0.as_secs_f32() * 1000.0;
+
+        // Just hurt, or just back from a battle, they get a moment before the next one starts.
+        let players: Vec<(Entity, Point, Rect, bool)> = (&entities, &position, &collisionbox, &keyboard_controlled, (&health).maybe()).join()
+            .filter(|(_, _, _, _, hp)| !hp.is_some_and(Health::dead))
+            .map(|(entity, pos, col, _, hp)| {
+                (entity, pos.0, Rect::from_center(pos.0, col.width, col.height), hp.is_some_and(Health::invulnerable))
+            })
+            .collect();
+        let walls: Vec<Rect> = (&position, &collisionbox, &static_collider).join()
+            .map(|(pos, col, _)| Rect::from_center(pos.0, col.width, col.height))
+            .collect();
+        let sees = |from: Point, to: Point| {
+            tilemap.sees(from, to) && walls.iter().all(|wall| wall.intersect_line(from, to).is_none())
+        };
+
+        for (entity, pos, col, foe, vel) in (&entities, &position, &collisionbox, &mut hostile, &mut velocity).join() {
+            foe.cooldown_ms = (foe.cooldown_ms - ms).max(0.0);
+            let distance = |to: Point| {
+                let (dx, dy) = ((to.x() - pos.0.x()) as f64, (to.y() - pos.0.y()) as f64);
+                dx.hypot(dy) as i32
+            };
+            let target = players.iter().min_by_key(|(_, point, _, _)| distance(*point));
+            let blocked = !vel.direction.is_empty() && movementflags.get(entity).is_some_and(|flag| !flag.moving);
+
+            foe.state = match (foe.state, target) {
+                (HostileState::Chasing, Some((_, point, _, _)))
+                    if distance(*point) <= foe.sight * LOSE_SIGHT_FACTOR && sees(pos.0, *point) => HostileState::Chasing,
+                (HostileState::Chasing, _) => HostileState::Returning,
+                (_, Some((_, point, _, _))) if distance(*point) <= foe.sight && sees(pos.0, *point) => HostileState::Chasing,
+                (HostileState::Returning, _) if distance(foe.home) <= foe.speed => HostileState::Idle,
+                (state, _) => state,
+            };
+
+            match (foe.state, target) {
+                (HostileState::Chasing, Some(&(player, point, player_rect, invulnerable))) => {
+                    let reach = Rect::from_center(pos.0, col.width + 2 * foe.reach as u32, col.height + 2 * foe.reach as u32);
+                    if !reach.has_intersection(player_rect) {
+                        walk(vel, Some(toward(pos.0, point, blocked)), foe.speed);
+                        continue;
+                    }
+                    walk(vel, None, 0);
+                    if encounter.contains(entity) {
+                        if !invulnerable && foe.cooldown_ms <= 0.0 {
+                            battle.request(entity);
+                        }
+                    } else if foe.cooldown_ms <= 0.0 {
+                        foe.cooldown_ms = ATTACK_COOLDOWN_MS;
+                        damage.insert(player, Damage {
+                            amount: foe.damage,
+                            knockback: Some((toward(pos.0, point, false), ATTACK_KNOCKBACK)),
+                        }).ok();
+                    }
+                },
+                (HostileState::Returning, _) => walk(vel, Some(toward(pos.0, foe.home, blocked)), foe.speed),
+                _ if walker.contains(entity) => vel.speed = WANDER_SPEED,
+                _ => walk(vel, None, 0),
+            }
+        }
+    }
+}
@@ -0,0 +1,197 @@
+use crate::components::*;
+use crate::textures::TextureRegistry;
+use crate::variables::Effect;
+use std::{collections::HashMap, fs};
+use regex::Regex;
+use sdl2::rect::{Point, Rect};
+use specs::{Builder, LazyUpdate, world::EntitiesRes};
+
+// Item definitions file format, one block per item:
+//
+// [apple]                          Item id, used to refer to the item everywhere else.
+// name: Apple
+// category: food                   Free-form grouping. Fruit spawns pick from the `food` category.
+// icon: food 80 0 16 16            Spritesheet and region (x y width height) of the icon.
+// stack: 10                        How many fit in one inventory slot.
+// description: A crisp red apple.
+// use: apples_eaten += 1           Optional. Makes the item usable from the menu, which uses up one of them
+//                                  and applies the effect to the world variables.
+// slot: weapon                     Optional. Makes the item equippable in the named slot.
+// heal: 5                          Optional. Makes the item usable in battle and from the menu, giving back
+//                                  this much health.
+
+// Items put down in a row are spread out this many pixels apart.
+const DROP_SPACING: i32 = 12;
+
+#[derive(Debug, Clone)]
+pub struct ItemDefinition {
+    pub id: String,
+    pub name: String,
+    pub category: String,
+    pub icon: Sprite,
+    pub stack_size: u32,
+    pub description: String,
+    pub on_use: Option<Effect>,
+    pub slot: Option<String>,
+    pub heal: Option<u32>,
+}
+
+#[derive(Debug, Default)]
+pub struct ItemDatabase {
+    items: HashMap<String, ItemDefinition>,
+}
+
+impl ItemDatabase {
+    pub fn load(path: &str, textures: &TextureRegistry) -> Result<Self, String> {
+        let source = fs::read_to_string(path).map_err(|e| format!("Could not read item file {}: {}", path, e))?;
+        Self::parse(&source, textures).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    fn parse(source: &str, textures: &TextureRegistry) -> Result<Self, String> {
+        let header_pattern = Regex::new(r"^\[(\w+)\]$").unwrap();
+        let field_pattern = Regex::new(r"^(\w+): *(.*)$").unwrap();
+        let icon_pattern = Regex::new(r"^(\w+) (\d+) (\d+) (\d+) (\d+)$").unwrap();
+
+        let mut database = ItemDatabase::default();
+        let mut current: Option<(String, HashMap<String, String>)> = None;
+
+        for (line_no, line) in source.lines().enumerate().map(|(i, l)| (i + 1, l.trim())) {
+            if line.is_empty() || line.starts_with("//") {
+                continue;
+            }
+            if let Some(cap) = header_pattern.captures(line) {
+                if let Some((id, fields)) = current.take() {
+                    database.insert(id, fields, &icon_pattern, textures)?;
+                }
+                if database.items.contains_key(&cap[1]) {
+                    return Err(format!("line {}: item [{}] is defined twice", line_no, &cap[1]));
+                }
+                current = Some((cap[1].to_string(), HashMap::new()));
+            } else if let Some(cap) = field_pattern.captures(line) {
+                match &mut current {
+                    Some((_, fields)) => { fields.insert(cap[1].to_string(), cap[2].to_string()); },
+                    None => return Err(format!("line {}: field outside of an item block", line_no)),
+                }
+            } else {
+                return Err(format!("line {}: could not parse \"{}\"", line_no, line));
+            }
+        }
+        if let Some((id, fields)) = current.take() {
+            database.insert(id, fields, &icon_pattern, textures)?;
+        }
+        Ok(database)
+    }
+
+    fn insert(
+        &mut self,
+        id: String,
+        mut fields: HashMap<String, String>,
+        icon_pattern: &Regex,
+        textures: &TextureRegistry,
+    ) -> Result<(), String> {
+        let mut field = |name: &str| fields.remove(name)
+            .ok_or_else(|| format!("item [{}] has no {}", id, name));
+
+        let icon = field("icon")?;
+        let cap = icon_pattern.captures(&icon)
+            .ok_or_else(|| format!("item [{}]: could not parse icon \"{}\"", id, icon))?;
+        let spritesheet = textures.get(&cap[1])
+            .ok_or_else(|| format!("item [{}]: unknown spritesheet \"{}\"", id, &cap[1]))?;
+        // The pattern only matches digits, so these can't fail.
+        let n = |i: usize| cap[i].parse::<i32>().unwrap();
+        let icon = Sprite {
+            spritesheet,
+            region: Rect::new(n(2), n(3), n(4) as u32, n(5) as u32),
+        };
+
+        let stack = field("stack")?;
+        let stack_size = stack.parse::<u32>()
+            .ok()
+            .filter(|&s| s > 0)
+            .ok_or_else(|| format!("item [{}]: stack must be a positive number, not \"{}\"", id, stack))?;
+
+        let on_use = match field("use") {
+            Ok(effect) => Some(Effect::parse(&effect).map_err(|e| format!("item [{}]: {}", id, e))?),
+            Err(_) => None,
+        };
+
+        let heal = match field("heal") {
+            Ok(heal) => Some(heal.parse::<u32>()
+                .map_err(|_| format!("item [{}]: heal should be a number, not \"{}\"", id, heal))?),
+            Err(_) => None,
+        };
+
+        let item = ItemDefinition {
+            name: field("name")?,
+            category: field("category")?,
+            description: field("description").unwrap_or_default(),
+            slot: field("slot").ok(),
+            id: id.clone(),
+            icon,
+            stack_size,
+            on_use,
+            heal,
+        };
+        if let Some(unknown) = fields.keys().next() {
+            return Err(format!("item [{}] has unknown field \"{}\"", id, unknown));
+        }
+        self.items.insert(id, item);
+        Ok(())
+    }
+
+    pub fn get(&self, id: &str) -> Option<&ItemDefinition> {
+        self.items.get(id)
+    }
+
+    pub fn in_category<'a>(&'a self, category: &'a str) -> impl Iterator<Item = &'a ItemDefinition> + 'a {
+        self.items.values().filter(move |item| item.category == category)
+    }
+}
+
+// Puts items in an inventory and tells the player about it. Returns how many did not fit.
+pub fn give_item(
+    inventory: &mut Inventory,
+    database: &ItemDatabase,
+    notifications: &mut Notifications,
+    id: &str,
+    count: u32,
+) -> u32 {
+    let item = match database.get(id) {
+        Some(item) => item,
+        None => {
+            println!("Tried to give unknown item \"{}\"", id);
+            return count;
+        },
+    };
+    let left_over = inventory.add(item, count);
+    let given = count - left_over;
+    if given == 1 {
+        notifications.push(format!("You found a {}!", item.name));
+    } else if given > 1 {
+        notifications.push(format!("You found {} x {}!", given, item.name));
+    }
+    if left_over > 0 {
+        notifications.push(format!("No room for {} x {}.", left_over, item.name));
+    }
+    left_over
+}
+
+// Puts items down as Collectibles in a row starting at `point`, spread out so they don't all land in
+// one spot. For what the dead leave behind, and loot there was no room for.
+pub fn drop_items(lazy: &LazyUpdate, entities: &EntitiesRes, database: &ItemDatabase, point: Point, items: &[(String, u32)]) {
+    let drops = items.iter().flat_map(|(item, count)| std::iter::repeat_n(item, *count as usize));
+    for (i, item) in drops.enumerate() {
+        if let Some(item) = database.get(item) {
+            lazy.create_entity(entities)
+                .with(Position(point + Point::new(i as i32 * DROP_SPACING, 0)))
+                .with(CollisionBox {
+                    width: item.icon.region.width(),
+                    height: item.icon.region.height(),
+                })
+                .with(Unplayable)
+                .with(Collectible { item: item.id.clone() })
+                .with(item.icon)
+                .build();
+        }
+    }
+}
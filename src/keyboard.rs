@@ -1,16 +1,19 @@
-use specs::{WriteStorage, System, ReadStorage, WriteExpect, ReadExpect, join::Join, Entities};
+use specs::{WriteStorage, System, ReadStorage, WriteExpect, ReadExpect, Read, LazyUpdate, Builder, join::Join, Entities};
 use crate::components::*;
-use std::{fs::File, collections::VecDeque, io::Read};
-//use sdl2::rect::{Rect, Point};
+use crate::dialogue::{ActiveDialogue, DialogueTree};
+use crate::variables::{Variable, WorldVariables};
+use crate::items::{ItemDatabase, drop_items, give_item};
+use crate::menu::{MenuState, entries};
+use std::collections::VecDeque;
 
 const PLAYER_MOVEMENT_SPEED: i32 = 5;
 
 pub struct Keyboard;
 
+
 use super::MovementCommand;
 use super::PlayerCommands;
 use super::Gamestate;
-use regex::Regex;
 
 impl<'a> System<'a> for Keyboard {
     type SystemData = (
@@ -20,13 +23,26 @@ impl<'a> System<'a> for Keyboard {
         ReadStorage<'a, Position>,
         WriteStorage<'a, Velocity>,
         WriteStorage<'a, Facing>,
-        ReadStorage<'a, CollisionBox>,
+        WriteStorage<'a, Health>,
         WriteStorage<'a, Interactable>,
         ReadStorage<'a, InteractionZone>,
         Entities<'a>,
         WriteExpect<'a, Gamestate>,
         ReadStorage<'a, Dialogue>,
-        WriteExpect<'a, VecDeque<Dialogue_Single_item>>
+        WriteExpect<'a, ActiveDialogue>,
+        WriteExpect<'a, WorldVariables>,
+        WriteExpect<'a, Dialogue_Helper>,
+        WriteStorage<'a, Inventory>,
+        ReadExpect<'a, ItemDatabase>,
+        ReadStorage<'a, LootTable>,
+        WriteExpect<'a, Notifications>,
+        WriteExpect<'a, MenuState>,
+        WriteStorage<'a, Equipment>,
+        Read<'a, LazyUpdate>,
+        WriteStorage<'a, Pickup>,
+        WriteStorage<'a, Lever>,
+        WriteExpect<'a, Signals>,
+        ReadStorage<'a, Links>,
     );
 
     fn run(&mut self, 
@@ -37,13 +53,26 @@ impl<'a> System<'a> for Keyboard {
         position,
         mut velocity,
         mut facing,
-        _collisionbox,
+        mut health,
         mut interactable,
         interactionzone,
-        _entities,
+        entities,
         mut gamestate,
         dialogue,
-        mut dialogue_list,
+        mut active_dialogue,
+        mut variables,
+        mut dialogue_layout,
+        mut inventory,
+        item_database,
+        loot,
+        mut notifications,
+        mut menu,
+        mut equipment,
+        lazy,
+        mut pickup,
+        mut lever,
+        mut signals,
+        links,
 
     ): Self::SystemData) {
         
@@ -79,7 +108,7 @@ impl<'a> System<'a> for Keyboard {
                     Some(PlayerCommands::Interact) => {
                         // Todo: Make this work better when there are more than one interactable object in the zone.
                         for interzone in (&interactionzone).join() {
-                            for (obj_pos, object, dialogue) in (&position, &mut interactable, (&dialogue).maybe()).join() {
+                            for (entity, obj_pos, object, dialogue, loot, pickup, lever, links) in (&entities, &position, &mut interactable, (&dialogue).maybe(), (&loot).maybe(), (&mut pickup).maybe(), (&mut lever).maybe(), (&links).maybe()).join() {
                                 if interzone.rect.contains_point(obj_pos.0) & ( // Is there an object in the interaction zone?
                                     ( // Can we interact more with it?
                                         (object.interactions < object.max_interactions) &
@@ -91,30 +120,79 @@ impl<'a> System<'a> for Keyboard {
                                     match (*object).interaction_type {
                                         InteractableType::Character => {
                                             if let Some(d) = dialogue {
-
-                                                //gamestate = Gamestate::Dialogue;
-
-                                                change_to_dialogue = true;
-                                                
-                                                let conversation_pattern = Regex::new(r#": "(.+)", (.+)\n(.+)"#).unwrap();
-
                                                 println!("Reading file {}", &d.dialogue_file);
-                                                let mut conv = String::new();
-                                                {
-                                                    let mut file = File::open(&d.dialogue_file).unwrap();
-                                                    file.read_to_string(&mut conv).unwrap();
-                                                }
-                                                
-                                                for cap in conversation_pattern.captures_iter(&conv) {
-                                                    dialogue_list.push_back(Dialogue_Single_item {
-                                                        speaker_name: (&cap[1]).into(),
-                                                        background_size: Size3::Small,
-                                                        dialogue_text: (&cap[3]).into(),
-                                                    });
+                                                match DialogueTree::load(&d.dialogue_file) {
+                                                    Ok(tree) => {
+                                                        change_to_dialogue = active_dialogue.start(tree, &mut variables);
+                                                    },
+                                                    Err(e) => println!("{}", e),
                                                 }
                                             };
                                         },
-                                        _ => (*object).interact()
+                                        InteractableType::Chest => {
+                                            if object.interactions == 0 {
+                                                variables.add("chests_opened", 1);
+                                                if let Some(links) = links {
+                                                    signals.send(links, true);
+                                                }
+                                                let found = loot.map(LootTable::roll).unwrap_or_default();
+                                                if found.is_empty() {
+                                                    notifications.push("The chest is empty.".to_string());
+                                                }
+                                                let mut left_over = Vec::new();
+                                                for (_, inv) in (&is_keyboardcontrolled, &mut inventory).join() {
+                                                    for (item, count) in &found {
+                                                        let left = give_item(inv, &item_database, &mut notifications, item, *count);
+                                                        if left > 0 {
+                                                            left_over.push((item.clone(), left));
+                                                        }
+                                                    }
+                                                }
+                                                drop_items(&lazy, &entities, &item_database, interzone.rect.center(), &left_over);
+                                            }
+                                            (*object).interact()
+                                        },
+                                        InteractableType::DestroyedOnUse => {
+                                            // Crates and the like may hold something too. The animator removes them.
+                                            if object.interactions == 0 {
+                                                let found = loot.map(LootTable::roll).unwrap_or_default();
+                                                let mut left_over = Vec::new();
+                                                for (_, inv) in (&is_keyboardcontrolled, &mut inventory).join() {
+                                                    for (item, count) in &found {
+                                                        let left = give_item(inv, &item_database, &mut notifications, item, *count);
+                                                        if left > 0 {
+                                                            left_over.push((item.clone(), left));
+                                                        }
+                                                    }
+                                                }
+                                                drop_items(&lazy, &entities, &item_database, interzone.rect.center(), &left_over);
+                                            }
+                                            (*object).interact()
+                                        },
+                                        InteractableType::Pickup => {
+                                            if let Some(pickup) = pickup {
+                                                for (_, inv) in (&is_keyboardcontrolled, &mut inventory).join() {
+                                                    pickup.count = give_item(inv, &item_database, &mut notifications, &pickup.item, pickup.count);
+                                                }
+                                                // Whatever didn't fit stays on the ground.
+                                                if pickup.count == 0 {
+                                                    entities.delete(entity).ok();
+                                                }
+                                            }
+                                            (*object).interact()
+                                        },
+                                        InteractableType::Lever => {
+                                            if let Some(lever) = lever {
+                                                lever.on = !lever.on;
+                                                if let Some(name) = &lever.variable {
+                                                    variables.set(name, Variable::Bool(lever.on));
+                                                }
+                                                if let Some(links) = links {
+                                                    signals.send(links, lever.on);
+                                                }
+                                            }
+                                            (*object).interact()
+                                        },
                                     }
                                     //println!("Interacted with a {:?}", *object);
                                     continue;
@@ -126,18 +204,122 @@ impl<'a> System<'a> for Keyboard {
                     Some(PlayerCommands::Menu) => {
                         *gamestate = Gamestate::Menu;
                     },
-                    None => {}
+                    Some(PlayerCommands::Status) => {
+                        *gamestate = Gamestate::Status;
+                    },
+                    // Swinging is up to the Combat system.
+                    Some(PlayerCommands::Cancel) | Some(PlayerCommands::Attack) | None => {}
                 };
 
             },
             Gamestate::Dialogue => {
+                let choices_shown = dialogue_layout.on_last_page() && dialogue_layout.page_revealed();
+
+                // Up and down pick between choices. Stop commands still have to reach the player,
+                // or they would keep walking once the conversation is over.
+                while let Some(Some(movement_command)) = movementcommands.pop_front() {
+                    match movement_command {
+                        MovementCommand::Move(Direction::Up) if choices_shown => active_dialogue.select_previous(),
+                        MovementCommand::Move(Direction::Down) if choices_shown => active_dialogue.select_next(),
+                        MovementCommand::Move(_) => {},
+                        MovementCommand::Stop(dir) => {
+                            for (_, vel) in (&is_keyboardcontrolled, &mut velocity).join() {
+                                vel.direction.retain(|&v| v != dir);
+                            }
+                        }
+                    }
+                }
+
                 if let Some(PlayerCommands::Interact) = &*playercommands {
-                    (*dialogue_list).pop_front();
-                    if (*dialogue_list).is_empty() {
-                        *gamestate = Gamestate::Running;
-                    } 
+                    // The first press finishes revealing the page. Long lines are split over several pages,
+                    // which are shown before moving on.
+                    if !dialogue_layout.page_revealed() {
+                        dialogue_layout.reveal_page();
+                    } else if !dialogue_layout.next_page() {
+                        dialogue_layout.reset();
+                        if !active_dialogue.advance(&mut variables) {
+                            *gamestate = Gamestate::Running;
+                        }
+                    }
                 }
             }
+            Gamestate::Menu => {
+                for (_, vel, inv, equip, hp, intzone) in (&is_keyboardcontrolled, &mut velocity, &mut inventory, (&mut equipment).maybe(), (&mut health).maybe(), &interactionzone).join() {
+                    let n_entries = entries(inv, equip.as_deref()).len();
+                    while let Some(Some(movement_command)) = movementcommands.pop_front() {
+                        match movement_command {
+                            MovementCommand::Move(Direction::Up) => menu.select_previous(),
+                            MovementCommand::Move(Direction::Down) => menu.select_next(n_entries),
+                            MovementCommand::Move(Direction::Left) => menu.previous_action(),
+                            MovementCommand::Move(Direction::Right) => menu.next_action(),
+                            MovementCommand::Stop(dir) => vel.direction.retain(|&v| v != dir),
+                        }
+                    }
+
+                    match &*playercommands {
+                        Some(PlayerCommands::Interact) => {
+                            let dropped = menu.perform(inv, equip, hp, &item_database, &mut variables, &mut notifications);
+                            if let Some(item) = dropped.as_ref().and_then(|id| item_database.get(id)) {
+                                // Put it down in front of the player, where it won't be picked straight back up.
+                                lazy.create_entity(&entities)
+                                    .with(Position(intzone.rect.center()))
+                                    .with(CollisionBox {
+                                        width: item.icon.region.width(),
+                                        height: item.icon.region.height(),
+                                    })
+                                    .with(Unplayable)
+                                    .with(Collectible { item: item.id.clone() })
+                                    .with(item.icon)
+                                    .build();
+                            }
+                        },
+                        Some(PlayerCommands::Menu) | Some(PlayerCommands::Cancel) => {
+                            *gamestate = Gamestate::Running;
+                        },
+                        Some(PlayerCommands::Attack) | Some(PlayerCommands::Status) | None => {},
+                    }
+                }
+            },
+            Gamestate::Status => {
+                // Nothing to pick, it is only looked at. Keys let go of still have to stop the player.
+                while let Some(Some(movement_command)) = movementcommands.pop_front() {
+                    if let MovementCommand::Stop(dir) = movement_command {
+                        for (_, vel) in (&is_keyboardcontrolled, &mut velocity).join() {
+                            vel.direction.retain(|&v| v != dir);
+                        }
+                    }
+                }
+                if matches!(*playercommands, Some(PlayerCommands::Status) | Some(PlayerCommands::Cancel) | Some(PlayerCommands::Interact)) {
+                    *gamestate = Gamestate::Running;
+                }
+            },
+            Gamestate::CollisionEditor | Gamestate::LevelEditor => {
+                // The editors handle their own input. Keys let go of still have to stop the player.
+                while let Some(Some(movement_command)) = movementcommands.pop_front() {
+                    if let MovementCommand::Stop(dir) = movement_command {
+                        for (_, vel) in (&is_keyboardcontrolled, &mut velocity).join() {
+                            vel.direction.retain(|&v| v != dir);
+                        }
+                    }
+                }
+            },
+            Gamestate::Battle => {
+                // The Battles system reads the commands. Keys let go of still have to stop the player.
+                for command in movementcommands.iter() {
+                    if let Some(MovementCommand::Stop(dir)) = command {
+                        for (_, vel) in (&is_keyboardcontrolled, &mut velocity).join() {
+                            vel.direction.retain(|v| v != dir);
+                        }
+                    }
+                }
+            },
+            Gamestate::GameOver => {
+                // Main handles getting back up. Nobody walks on meanwhile.
+                movementcommands.clear();
+                for (_, vel) in (&is_keyboardcontrolled, &mut velocity).join() {
+                    vel.direction.clear();
+                }
+            },
             _ => {println!("Not running, player commands disabled.");}
         }
         if change_to_dialogue {
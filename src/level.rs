@@ -0,0 +1,295 @@
+use crate::components::*;
+use crate::foes::FoeDatabase;
+use std::{collections::BTreeMap, fs, path::Path};
+use regex::Regex;
+use specs::prelude::*;
+use specs_derive::Component;
+use sdl2::rect::Point;
+
+// Level file format, one entity per line:
+//
+// chest 148 -116 loot="potion; apple 1-3 w2" links="gate"
+//
+// The kind (see KINDS), the world position of its middle, and any properties as name="value". Values
+// can't contain quotes. Lines starting with // are comments.
+
+// Every kind of entity a level can place, with the properties it takes and what new ones start out as.
+// Properties left empty aren't set, so the entity gets its usual default.
+//
+//   spawn            Where the player starts.
+//   npc              `dialogue`: dialogue file. `wander`: true or false. `sheet`: the sprite sheet it
+//                    looks like, see src/animations.rs. `loot`: what it drops when it dies. `party`: true
+//                    to have it fight alongside the player in battles.
+//   enemy            `sheet`, `wander` and `loot` like an npc. `sight`: how near, in pixels, the player
+//                    has to come to be chased. `damage`: how much each hit takes. `encounter`: comma
+//                    separated foes, see src/foes.rs, to fight in a battle when it catches the player.
+//   chest, crate     `loot`: loot table, see LootTable::parse.
+//   fruit            A random food item.
+//   pickup           `item`: item id. `count`.
+//   lever, pressure_plate
+//                    `links`: comma separated names to send signals to. `signal`: activate, deactivate
+//                    or toggle, to always send that instead of following the source's state. Levers
+//                    also take `variable`, a world variable kept set to whether it is on.
+//   door             `name`, which signals are sent to.
+//   spawner          `name`, `item`. Drops one each time it is signalled.
+//
+// Chests also take `links`, and anything that can be interacted with `interactions` and `max_interactions`.
+// Anything given `health` can be hurt, and dies when it runs out.
+pub const KINDS: &[(&str, &[(&str, &str)])] = &[
+    ("spawn", &[]),
+    ("npc", &[("dialogue", "assets/test_dialogue.txt"), ("wander", "true"), ("sheet", "reaper"), ("health", ""), ("loot", ""), ("party", ""), ("interactions", ""), ("max_interactions", "")]),
+    ("enemy", &[("sheet", "reaper"), ("wander", "false"), ("health", "3"), ("loot", ""), ("sight", ""), ("damage", ""), ("encounter", "")]),
+    ("chest", &[("loot", ""), ("links", ""), ("signal", ""), ("interactions", ""), ("max_interactions", "")]),
+    ("crate", &[("loot", "")]),
+    ("fruit", &[]),
+    ("pickup", &[("item", "potion"), ("count", "1")]),
+    ("lever", &[("links", "door"), ("signal", ""), ("variable", ""), ("interactions", ""), ("max_interactions", "")]),
+    ("pressure_plate", &[("links", "door"), ("signal", "")]),
+    ("door", &[("name", "door")]),
+    ("spawner", &[("name", "spawner"), ("item", "apple")]),
+];
+
+pub fn kind_properties(kind: &str) -> Option<&'static [(&'static str, &'static str)]> {
+    KINDS.iter().find(|(k, _)| *k == kind).map(|(_, properties)| *properties)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Placement {
+    pub kind: String,
+    pub position: Point,
+    pub properties: BTreeMap<String, String>,
+}
+
+impl Placement {
+    // A new entity of the kind, with the default properties from KINDS.
+    pub fn new(kind: &str, position: Point) -> Self {
+        let properties = kind_properties(kind).unwrap_or(&[]).iter()
+            .filter(|(_, default)| !default.is_empty())
+            .map(|(name, default)| (name.to_string(), default.to_string()))
+            .collect();
+        Placement {
+            kind: kind.to_string(),
+            position,
+            properties,
+        }
+    }
+
+    // The properties its kind takes, then any others it was given.
+    pub fn property_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = kind_properties(&self.kind).unwrap_or(&[]).iter()
+            .map(|(name, _)| name.to_string())
+            .collect();
+        for name in self.properties.keys() {
+            if !names.contains(name) {
+                names.push(name.clone());
+            }
+        }
+        names
+    }
+
+    pub fn property(&self, name: &str) -> Option<&str> {
+        self.properties.get(name).map(String::as_str)
+    }
+
+    fn required(&self, name: &str) -> Result<&str, String> {
+        self.property(name).ok_or_else(|| format!("needs a \"{}\" property", name))
+    }
+
+    fn number<T: std::str::FromStr>(&self, name: &str) -> Result<Option<T>, String> {
+        match self.property(name) {
+            Some(value) => value.parse().map(Some).map_err(|_| format!("\"{}\" should be a number, not \"{}\"", name, value)),
+            None => Ok(None),
+        }
+    }
+
+    fn links(&self) -> Result<Links, String> {
+        let signal = match self.property("signal") {
+            Some(token) => Some(Signal::from_token(token).ok_or_else(|| format!("unknown signal \"{}\"", token))?),
+            None => None,
+        };
+        Ok(Links {
+            targets: self.property("links")
+                .map(|links| links.split(',').map(str::trim).filter(|l| !l.is_empty()).map(String::from).collect())
+                .unwrap_or_default(),
+            signal,
+        })
+    }
+}
+
+// Remembers what an entity was spawned from, so the level editor can save it and undo changes to it.
+#[derive(Component, Debug, Clone)]
+#[storage(VecStorage)]
+pub struct Placed(pub Placement);
+
+// Marks where the player starts.
+#[derive(Component, Debug, Default)]
+#[storage(NullStorage)]
+pub struct PlayerStart;
+
+pub fn spawn(world: &mut World, placement: &Placement) -> Result<Entity, String> {
+    spawn_kind(world, placement).map_err(|e| {
+        format!("{} at ({}, {}): {}", placement.kind, placement.position.x(), placement.position.y(), e)
+    })
+}
+
+fn spawn_kind(world: &mut World, placement: &Placement) -> Result<Entity, String> {
+    let (x, y) = (placement.position.x(), placement.position.y());
+    let loot = |default: &str| LootTable::parse(placement.property("loot").unwrap_or(default));
+
+    let entity = match placement.kind.as_str() {
+        "spawn" => world.create_entity()
+            .with(Position(placement.position))
+            .with(PlayerStart)
+            .build(),
+        "npc" => {
+            let wander = placement.property("wander") != Some("false");
+            let npc = crate::spawn_npc(world, x, y, placement.required("dialogue")?, wander, placement.property("sheet").unwrap_or("reaper"))?;
+            if placement.property("loot").is_some() {
+                world.write_storage::<LootTable>().insert(npc, loot("")?).ok();
+            }
+            if placement.property("party") == Some("true") {
+                crate::join_party(world, npc);
+            }
+            npc
+        },
+        "enemy" => {
+            let wander = placement.property("wander") == Some("true");
+            let enemy = crate::spawn_enemy(world, x, y, wander, placement.property("sheet").unwrap_or("reaper"))?;
+            if let Some(hostile) = world.write_storage::<Hostile>().get_mut(enemy) {
+                hostile.sight = placement.number("sight")?.unwrap_or(hostile.sight);
+                hostile.damage = placement.number("damage")?.unwrap_or(hostile.damage);
+            }
+            if placement.property("loot").is_some() {
+                world.write_storage::<LootTable>().insert(enemy, loot("")?).ok();
+            }
+            if let Some(foes) = placement.property("encounter") {
+                let foes: Vec<String> = foes.split(',').map(str::trim).filter(|f| !f.is_empty()).map(String::from).collect();
+                if let Some(unknown) = foes.iter().find(|f| world.read_resource::<FoeDatabase>().get(f).is_none()) {
+                    return Err(format!("unknown foe \"{}\"", unknown));
+                }
+                world.write_storage::<Encounter>().insert(enemy, Encounter { foes }).ok();
+            }
+            enemy
+        },
+        "chest" => crate::spawn_chest(world, x, y, loot(crate::DEFAULT_CHEST_LOOT)?, placement.links()?)?,
+        "crate" => crate::spawn_crate(world, x, y, loot(crate::DEFAULT_CRATE_LOOT)?)?,
+        "fruit" => crate::spawn_fruit(world, x, y)?,
+        "pickup" => {
+            let count = placement.number("count")?.unwrap_or(1);
+            crate::spawn_pickup(world, x, y, placement.required("item")?, count)?
+        },
+        "lever" => crate::spawn_lever(world, x, y, placement.links()?, placement.property("variable"))?,
+        "pressure_plate" => crate::spawn_pressure_plate(world, x, y, placement.links()?)?,
+        "door" => crate::spawn_door(world, x, y, placement.required("name")?)?,
+        "spawner" => crate::spawn_item_spawner(world, x, y, placement.required("name")?, placement.required("item")?)?,
+        kind => {
+            let known: Vec<&str> = KINDS.iter().map(|(k, _)| *k).collect();
+            return Err(format!("unknown kind \"{}\", expected one of {}", kind, known.join(", ")));
+        },
+    };
+
+    if let Some(interactable) = world.write_storage::<Interactable>().get_mut(entity) {
+        if let Some(interactions) = placement.number("interactions")? {
+            interactable.interactions = interactions;
+        }
+        if let Some(max_interactions) = placement.number("max_interactions")? {
+            interactable.max_interactions = max_interactions;
+        }
+    }
+    if let Some(health) = placement.number("health")? {
+        world.write_storage::<Health>().insert(entity, Health::new(health)).ok();
+    }
+    world.write_storage::<Placed>().insert(entity, Placed(placement.clone())).ok();
+    Ok(entity)
+}
+
+pub fn spawn_all(world: &mut World, placements: &[Placement]) -> Result<(), String> {
+    for placement in placements {
+        spawn(world, placement)?;
+    }
+    Ok(())
+}
+
+// Puts the player on the level's spawn point, if it has one.
+pub fn place_player(world: &mut World) {
+    let start = (&world.read_storage::<Position>(), &world.read_storage::<PlayerStart>()).join()
+        .map(|(pos, _)| *pos)
+        .next();
+    if let Some(start) = start {
+        let mut position = world.write_storage::<Position>();
+        for (pos, _) in (&mut position, &world.read_storage::<KeyboardControlled>()).join() {
+            *pos = start;
+        }
+    }
+}
+
+// What is placed in the world right now, where it currently is.
+pub fn snapshot(world: &World) -> Vec<Placement> {
+    let position = world.read_storage::<Position>();
+    let placed = world.read_storage::<Placed>();
+    (&world.entities(), &placed).join()
+        .map(|(entity, Placed(placement))| Placement {
+            position: position.get(entity).map_or(placement.position, |pos| pos.0),
+            ..placement.clone()
+        })
+        .collect()
+}
+
+// Removes everything that was placed and spawns the placements instead.
+pub fn restore(world: &mut World, placements: &[Placement]) -> Result<(), String> {
+    let placed: Vec<Entity> = (&world.entities(), &world.read_storage::<Placed>()).join()
+        .map(|(entity, _)| entity)
+        .collect();
+    world.delete_entities(&placed).ok();
+    spawn_all(world, placements)
+}
+
+pub fn load(path: &str) -> Result<Vec<Placement>, String> {
+    let contents = fs::read_to_string(path).map_err(|e| format!("Could not read level file {}: {}", path, e))?;
+    parse(&contents).map_err(|e| format!("{}: {}", path, e))
+}
+
+// A level file takes the place of the objects in the map, once there is one.
+pub fn exists(path: &str) -> bool {
+    Path::new(path).exists()
+}
+
+fn parse(source: &str) -> Result<Vec<Placement>, String> {
+    let line_pattern = Regex::new(r"^(\w+) (-?\d+) (-?\d+)((?: \w+=\x22[^\x22]*\x22)*)$").unwrap();
+    let property_pattern = Regex::new(r"(\w+)=\x22([^\x22]*)\x22").unwrap();
+
+    let mut placements = Vec::new();
+    for (line_no, line) in source.lines().enumerate().map(|(i, l)| (i + 1, l.trim())) {
+        if line.is_empty() || line.starts_with("//") {
+            continue;
+        }
+        let cap = line_pattern.captures(line)
+            .ok_or_else(|| format!("line {}: could not parse \"{}\"", line_no, line))?;
+        if kind_properties(&cap[1]).is_none() {
+            return Err(format!("line {}: unknown kind \"{}\"", line_no, &cap[1]));
+        }
+        // The pattern only matches digits here.
+        let position = Point::new(cap[2].parse().unwrap(), cap[3].parse().unwrap());
+        let properties = property_pattern.captures_iter(&cap[4])
+            .map(|p| (p[1].to_string(), p[2].to_string()))
+            .collect();
+        placements.push(Placement {
+            kind: cap[1].to_string(),
+            position,
+            properties,
+        });
+    }
+    Ok(placements)
+}
+
+pub fn save(path: &str, placements: &[Placement]) -> Result<(), String> {
+    let mut contents = String::from("// Placed with the level editor. See src/level.rs for the format.\n");
+    for placement in placements {
+        contents.push_str(&format!("{} {} {}", placement.kind, placement.position.x(), placement.position.y()));
+        for (name, value) in &placement.properties {
+            contents.push_str(&format!(" {}=\"{}\"", name, value.replace('"', "'")));
+        }
+        contents.push('\n');
+    }
+    fs::write(path, contents).map_err(|e| format!("Could not save level file {}: {}", path, e))
+}
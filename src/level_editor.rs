@@ -0,0 +1,268 @@
+use crate::components::*;
+use crate::level::{self, KINDS, Placed, Placement};
+use specs::prelude::*;
+use sdl2::keyboard::Keycode;
+use sdl2::rect::{Point, Rect};
+
+// Size of the marker for entities with neither a sprite nor a collision box, like spawn points.
+pub const MARKER_SIZE: u32 = 16;
+// How many steps back undo remembers.
+const UNDO_LIMIT: usize = 100;
+
+struct Drag {
+    entity: Entity,
+    offset: Point,
+    moved: bool,
+}
+
+// Places the level's entities while in Gamestate::LevelEditor. The mouse wheel picks a kind from the
+// palette, and number keys 1 to 9 and 0 pick one of the first ten, so the ones after that, like
+// spawner, are only on the wheel. Clicking empty ground places one. Clicking an entity selects it,
+// dragging moves it and right click or Delete removes it. Up and Down pick a property of the selected
+// entity, Enter starts and finishes typing a new value and Escape cancels. Ctrl+Z undoes, Ctrl+Y
+// redoes and Ctrl+S saves to the level file. All points are in world coordinates.
+#[derive(Default)]
+pub struct LevelEditor {
+    pub palette_index: usize,
+    pub selected: Option<Entity>,
+    pub property_index: usize,
+    // The value being typed, while editing a property.
+    pub editing: Option<String>,
+    drag: Option<Drag>,
+    undo: Vec<Vec<Placement>>,
+    redo: Vec<Vec<Placement>>,
+}
+
+// What counts as clicking on an entity, and what the editor outlines.
+pub fn placed_rect(pos: &Position, col: Option<&CollisionBox>, sprite: Option<&Sprite>) -> Rect {
+    match (col, sprite) {
+        (Some(col), _) => Rect::from_center(pos.0, col.width, col.height),
+        (None, Some(sprite)) => Rect::from_center(pos.0, sprite.region.width(), sprite.region.height()),
+        (None, None) => Rect::from_center(pos.0, MARKER_SIZE, MARKER_SIZE),
+    }
+}
+
+fn placed_at(world: &World, point: Point) -> Option<(Entity, Rect)> {
+    let entities = world.entities();
+    let position = world.read_storage::<Position>();
+    let collision = world.read_storage::<CollisionBox>();
+    let sprite = world.read_storage::<Sprite>();
+    let placed = world.read_storage::<Placed>();
+    (&entities, &position, collision.maybe(), sprite.maybe(), &placed).join()
+        .map(|(entity, pos, col, sprite, _)| (entity, placed_rect(pos, col, sprite)))
+        .filter(|(_, rect)| rect.contains_point(point))
+        .last()
+}
+
+impl LevelEditor {
+    pub fn kind(&self) -> &'static str {
+        KINDS[self.palette_index].0
+    }
+
+    pub fn typing(&self) -> bool {
+        self.editing.is_some()
+    }
+
+    pub fn scroll_palette(&mut self, steps: i32) {
+        let count = KINDS.len() as i32;
+        self.palette_index = (self.palette_index as i32 - steps).rem_euclid(count) as usize;
+    }
+
+    // The selected entity as it would be saved.
+    pub fn selected_placement(&self, world: &World) -> Option<Placement> {
+        let entity = self.selected?;
+        let Placed(placement) = world.read_storage::<Placed>().get(entity)?.clone();
+        let position = world.read_storage::<Position>().get(entity).map_or(placement.position, |pos| pos.0);
+        Some(Placement { position, ..placement })
+    }
+
+    fn select(&mut self, entity: Option<Entity>) {
+        if self.selected != entity {
+            self.property_index = 0;
+            self.editing = None;
+        }
+        self.selected = entity;
+    }
+
+    // Call before every change, so it can be undone.
+    fn remember(&mut self, world: &World) {
+        self.undo.push(level::snapshot(world));
+        if self.undo.len() > UNDO_LIMIT {
+            self.undo.remove(0);
+        }
+        self.redo.clear();
+    }
+
+    pub fn mouse_down(&mut self, world: &mut World, point: Point) -> Result<(), String> {
+        match placed_at(world, point) {
+            Some((entity, rect)) => {
+                self.select(Some(entity));
+                self.drag = Some(Drag { entity, offset: point - rect.center(), moved: false });
+            },
+            None => {
+                self.remember(world);
+                let entity = level::spawn(world, &Placement::new(self.kind(), point));
+                if entity.is_err() {
+                    self.undo.pop();
+                }
+                self.select(Some(entity?));
+            },
+        }
+        Ok(())
+    }
+
+    pub fn mouse_moved(&mut self, world: &mut World, point: Point) {
+        let entity = match &self.drag {
+            Some(drag) => drag.entity,
+            None => return,
+        };
+        // A click without moving shouldn't leave a step to undo.
+        if self.drag.as_ref().is_some_and(|drag| !drag.moved) {
+            self.remember(world);
+        }
+        if let Some(drag) = &mut self.drag {
+            drag.moved = true;
+            if let Some(pos) = world.write_storage::<Position>().get_mut(entity) {
+                pos.0 = point - drag.offset;
+            }
+        }
+    }
+
+    pub fn mouse_up(&mut self) {
+        self.drag = None;
+    }
+
+    pub fn delete_at(&mut self, world: &mut World, point: Point) {
+        if let Some((entity, _)) = placed_at(world, point) {
+            self.delete(world, entity);
+        }
+    }
+
+    fn delete(&mut self, world: &mut World, entity: Entity) {
+        self.remember(world);
+        world.delete_entity(entity).ok();
+        if self.selected == Some(entity) {
+            self.select(None);
+        }
+        self.drag = None;
+    }
+
+    pub fn undo(&mut self, world: &mut World) -> Result<(), String> {
+        if let Some(placements) = self.undo.pop() {
+            self.redo.push(level::snapshot(world));
+            self.restore(world, &placements)?;
+        }
+        Ok(())
+    }
+
+    pub fn redo(&mut self, world: &mut World) -> Result<(), String> {
+        if let Some(placements) = self.redo.pop() {
+            self.undo.push(level::snapshot(world));
+            self.restore(world, &placements)?;
+        }
+        Ok(())
+    }
+
+    // Every placed entity is spawned again, so none of the old ones can stay selected.
+    fn restore(&mut self, world: &mut World, placements: &[Placement]) -> Result<(), String> {
+        self.select(None);
+        self.drag = None;
+        level::restore(world, placements)
+    }
+
+    pub fn type_text(&mut self, text: &str) {
+        if let Some(value) = &mut self.editing {
+            value.push_str(text);
+        }
+    }
+
+    // Starts typing a new value for the chosen property, or finishes it by spawning the entity again
+    // with the new value. An empty value unsets the property.
+    fn enter(&mut self, world: &mut World) -> Result<(), String> {
+        let mut placement = match self.selected_placement(world) {
+            Some(placement) => placement,
+            None => return Ok(()),
+        };
+        let name = match placement.property_names().get(self.property_index) {
+            Some(name) => name.clone(),
+            None => return Ok(()),
+        };
+        let value = match self.editing.take() {
+            Some(value) => value,
+            None => {
+                self.editing = Some(placement.property(&name).unwrap_or("").to_string());
+                return Ok(());
+            },
+        };
+
+        if value.is_empty() {
+            placement.properties.remove(&name);
+        } else {
+            placement.properties.insert(name, value);
+        }
+        self.remember(world);
+        match level::spawn(world, &placement) {
+            Ok(entity) => {
+                if let Some(old) = self.selected {
+                    world.delete_entity(old).ok();
+                }
+                self.selected = Some(entity);
+                Ok(())
+            },
+            Err(e) => {
+                self.undo.pop();
+                Err(e)
+            },
+        }
+    }
+
+    pub fn key_down(&mut self, world: &mut World, key: Keycode, ctrl: bool, path: &str) -> Result<(), String> {
+        if self.typing() {
+            match key {
+                Keycode::Return | Keycode::KpEnter => self.enter(world)?,
+                Keycode::Escape => self.editing = None,
+                Keycode::Backspace => {
+                    if let Some(value) = &mut self.editing {
+                        value.pop();
+                    }
+                },
+                _ => {},
+            }
+            return Ok(());
+        }
+
+        let property_count = self.selected_placement(world).map_or(0, |p| p.property_names().len());
+        match key {
+            Keycode::Z if ctrl => self.undo(world)?,
+            Keycode::Y if ctrl => self.redo(world)?,
+            Keycode::S if ctrl => {
+                level::save(path, &level::snapshot(world))?;
+                world.write_resource::<Notifications>().push(format!("Saved {}", path));
+            },
+            Keycode::Delete | Keycode::Backspace => {
+                if let Some(entity) = self.selected {
+                    self.delete(world, entity);
+                }
+            },
+            Keycode::Up if property_count > 0 => {
+                self.property_index = (self.property_index + property_count - 1) % property_count;
+            },
+            Keycode::Down if property_count > 0 => {
+                self.property_index = (self.property_index + 1) % property_count;
+            },
+            Keycode::Return | Keycode::KpEnter => self.enter(world)?,
+            _ => {
+                let digits = [
+                    Keycode::Num1, Keycode::Num2, Keycode::Num3, Keycode::Num4, Keycode::Num5,
+                    Keycode::Num6, Keycode::Num7, Keycode::Num8, Keycode::Num9, Keycode::Num0,
+                ];
+                if let Some(index) = digits.iter().position(|&digit| digit == key) {
+                    if index < KINDS.len() {
+                        self.palette_index = index;
+                    }
+                }
+            },
+        }
+        Ok(())
+    }
+}
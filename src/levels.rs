@@ -0,0 +1,108 @@
+use crate::components::{Health, Stats};
+use std::fs;
+use regex::Regex;
+
+// Levels file format, one line per level the party can reach:
+//
+// 2: 10 xp, hp +3, attack +1       The level, the experience it takes in total, and how much goes up on
+//                                  reaching it: hp, mp, attack, defence or speed. Anything not listed
+//                                  stays as it was.
+//
+// Lines starting with // are comments. Everyone starts at level 1, so the levels start at 2 and follow
+// on from each other, each taking more experience than the one before.
+
+#[derive(Debug, Clone, Default)]
+pub struct Growth {
+    pub health: u32,
+    pub mp: u32,
+    pub attack: u32,
+    pub defence: u32,
+    pub speed: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct Level {
+    pub level: u32,
+    pub experience: u32,
+    pub growth: Growth,
+}
+
+#[derive(Debug, Default)]
+pub struct LevelTable {
+    levels: Vec<Level>,
+}
+
+impl LevelTable {
+    pub fn load(path: &str) -> Result<Self, String> {
+        let source = fs::read_to_string(path).map_err(|e| format!("Could not read levels file {}: {}", path, e))?;
+        Self::parse(&source).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    fn parse(source: &str) -> Result<Self, String> {
+        let level_pattern = Regex::new(r"^(\d+): (\d+) xp((?:, \w+ \+\d+)*)$").unwrap();
+        let growth_pattern = Regex::new(r", (\w+) \+(\d+)").unwrap();
+
+        let mut table = LevelTable::default();
+        for (line_no, line) in source.lines().enumerate().map(|(i, l)| (i + 1, l.trim())) {
+            if line.is_empty() || line.starts_with("//") {
+                continue;
+            }
+            let number = |text: &str| text.parse::<u32>().map_err(|e| format!("line {}: {}", line_no, e));
+            let cap = level_pattern.captures(line)
+                .ok_or_else(|| format!("line {}: could not parse \"{}\"", line_no, line))?;
+
+            let (level, experience) = (number(&cap[1])?, number(&cap[2])?);
+            let (expected, previous) = table.levels.last().map_or((2, 0), |last| (last.level + 1, last.experience));
+            if level != expected {
+                return Err(format!("line {}: expected level {}, not {}", line_no, expected, level));
+            }
+            if experience <= previous {
+                return Err(format!("line {}: level {} should take more than {} xp", line_no, level, previous));
+            }
+
+            let mut growth = Growth::default();
+            for stat in growth_pattern.captures_iter(&cap[3]) {
+                let amount = number(&stat[2])?;
+                match &stat[1] {
+                    "hp" => growth.health += amount,
+                    "mp" => growth.mp += amount,
+                    "attack" => growth.attack += amount,
+                    "defence" => growth.defence += amount,
+                    "speed" => growth.speed += amount,
+                    other => return Err(format!("line {}: unknown stat \"{}\"", line_no, other)),
+                }
+            }
+            table.levels.push(Level { level, experience, growth });
+        }
+        Ok(table)
+    }
+
+    // Experience it takes to get past `level`, or None at the highest.
+    pub fn next(&self, level: u32) -> Option<u32> {
+        self.levels.iter().find(|next| next.level == level + 1).map(|next| next.experience)
+    }
+
+    // Goes up as many levels as the experience is enough for, filling up health and MP for each.
+    // Returns the levels reached.
+    pub fn level_up(&self, stats: &mut Stats, mut health: Option<&mut Health>) -> Vec<u32> {
+        let mut reached = Vec::new();
+        while let Some(next) = self.levels.iter().find(|next| next.level == stats.level + 1) {
+            if stats.experience < next.experience {
+                break;
+            }
+            let growth = &next.growth;
+            stats.level = next.level;
+            stats.max_mp += growth.mp;
+            stats.mp = stats.max_mp;
+            stats.attack += growth.attack;
+            stats.defence += growth.defence;
+            stats.speed += growth.speed;
+            if let Some(hp) = health.as_deref_mut() {
+                hp.max += growth.health;
+                hp.current = hp.max;
+            }
+            reached.push(next.level);
+        }
+        reached
+    }
+}
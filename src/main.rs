@@ -2,92 +2,97 @@
 // - Implement an editor to easily add collision maps for background images. This requires some GUI and some serialization.
 // ->> Add dialogue support. Read from file, modify game state, that kinda thing.
 // - Add animations for attacks. This path will go by animation in PS (or something). Quite the detour, but very interesting.
+//   ->> Swinging works now (C), with a hitbox that knocks things back. Aseprite turned out easier than PS.
 
 
+mod animations;
 mod animator;
+mod battle;
+mod aseprite;
+mod collision_editor;
 mod collisions;
+mod combat;
 mod components;
+mod dialogue;
+mod foes;
+mod fonts;
+mod health;
+mod hostiles;
+mod items;
 mod keyboard;
+mod level;
+mod level_editor;
+mod levels;
+mod menu;
 mod physics;
+mod pressure_plates;
 mod randomwalker;
 mod renderer;
+mod savegame;
+mod switches;
+mod textures;
+mod tilemap;
+mod tiled;
+mod typewriter;
 mod collectibles;
 mod update_interaction;
+mod variables;
 
 use rand::{Rng, thread_rng};
 use sdl2::event::Event;
+use sdl2::mouse::MouseButton;
 use sdl2::pixels::Color;
 use sdl2::{
     image::{LoadTexture},
-    keyboard::Keycode,
+    keyboard::{Keycode, Mod},
     rect::{Point, Rect},
     pixels::PixelFormatEnum,
 };
 use std::time::{Duration, Instant};
-use std::collections::{VecDeque, HashMap};
+use std::collections::VecDeque;
 use noise::{Perlin, NoiseFn};
 
 use crate::components::*;
+use crate::animations::SpriteSheets;
+use crate::textures::{TextureHandle, TextureRegistry};
 use specs::prelude::*;
 
 
 
 extern crate sdl2;
 
-const SPRITE_WIDTH_PLAYER: i32 = 26;
-const SPRITE_HEIGHT_PLAYER: i32 = 36;
-
-const SPRITE_WIDTH_REAPER: i32 = 32;
-const SPRITE_HEIGHT_REAPER: i32 = 36;
-
 const SPRITE_HEIGHT_FRUIT: i32 = 16;
 const SPRITE_WIDTH_FRUIT: i32 = 16;
 
-const SPRITE_HEIGHT_CHEST: i32 = 24;
-const SPRITE_WIDTH_CHEST: i32 = 24;
-
-const ANIMATION_N_FRAMES: u8 = 3;
-const ANIMATION_N_FRAMES_CHEST: u8 = 4;
+const TEXTURE_MANIFEST: &str = "assets/textures.txt";
+const SPRITE_SHEETS: &str = "assets/sprites.txt";
+const START_MAP: &str = "assets/maps/start.tmj";
+const START_COLLISION: &str = "assets/maps/start_collision.txt";
+// Saved by the level editor. Until there is one, the entities come from the objects in START_MAP.
+const START_LEVEL: &str = "assets/maps/start_level.txt";
 
+const PLAYER_INVENTORY_SLOTS: usize = 20;
+const PLAYER_HEALTH: u32 = 10;
+const PLAYER_STATS: Stats = Stats { level: 1, experience: 0, mp: 6, max_mp: 6, attack: 4, defence: 2, speed: 4 };
+// NPCs that join the party.
+const PARTY_MEMBER_HEALTH: u32 = 8;
+const PARTY_MEMBER_STATS: Stats = Stats { level: 1, experience: 0, mp: 4, max_mp: 4, attack: 3, defence: 2, speed: 3 };
 
-fn generate_animation(
-    spritesheet_idx: usize,
-    top_left: Rect,
-    dir: Direction,
-    sprite_width: i32,
-) -> Vec<Sprite> {
-    let mut frames: Vec<Sprite> = Vec::new();
+// What enemies are like unless the level says otherwise.
+const ENEMY_HEALTH: u32 = 3;
+const ENEMY_SIGHT: i32 = 150;
+const ENEMY_REACH: i32 = 4;
+const ENEMY_DAMAGE: u32 = 1;
+const ENEMY_SPEED: i32 = 3;
+const DEFAULT_CHEST_LOOT: &str = "potion; apple 1-3 w3; cherries 2-5 w2; elixir w1; sword w1";
 
-    let y_offset = top_left.y() + direction_to_animation_row(dir);
-    let (width, height) = top_left.size();
+// The containers sheet has a clip for every look of the crate, lever, door and pressure plate.
+const CONTAINERS_SHEET: &str = "containers";
+const DEFAULT_CRATE_LOOT: &str = "apple w2; bread w2; cheese w1";
 
-    for i in 0..ANIMATION_N_FRAMES as i32 {
-        frames.push(Sprite {
-            spritesheet: spritesheet_idx,
-            region: Rect::new(top_left.x() + sprite_width * i, y_offset, width, height),
-        })
-    }
-    frames
-}
-
-fn generate_animation_chest(
-    spritesheet_idx: usize
-) -> Vec<Sprite> {
-    let mut frames: Vec<Sprite> = Vec::new();
-
-    for i in 0..ANIMATION_N_FRAMES_CHEST as i32 {
-        frames.push(Sprite {
-            spritesheet: spritesheet_idx,
-            region: Rect::new(
-                SPRITE_WIDTH_CHEST * i,
-                0,
-                SPRITE_WIDTH_CHEST as u32,
-                SPRITE_HEIGHT_CHEST as u32),
-        })
-    }
+// Animations move along by at most this much per tick, so they don't skip ahead after a stall.
+const MAX_TICK: Duration = Duration::from_millis(250);
 
-    frames
-}
 
 pub enum MovementCommand {
     Stop(Direction),
@@ -97,60 +102,14 @@ pub enum MovementCommand {
 pub enum PlayerCommands {
     Interact,
     Menu,
-}
-
-enum Spawner {
-    Fruit,
-    Chests,
-}
-
-fn direction_to_animation_row(dir: Direction) -> i32 {
-    use Direction::*;
-    match dir {
-        Down => 0,
-        Left => SPRITE_HEIGHT_PLAYER as i32,
-        Right => 2 * SPRITE_HEIGHT_PLAYER as i32,
-        Up => 3 * SPRITE_HEIGHT_PLAYER as i32,
-    }
+    Cancel,
+    Attack,
+    Status,
 }
 
 pub fn add_player(world: &mut World) -> Result<(), String> {
-    let player_texture_idx = 0;
-
-    let player_top_left = Rect::new(
-        0,
-        0,
-        SPRITE_WIDTH_PLAYER as u32,
-        SPRITE_HEIGHT_PLAYER as u32,
-    );
-
-    let player_animations = MovementAnimation {
-        current_frame: 0,
-        up_frames: generate_animation(
-            player_texture_idx,
-            player_top_left,
-            Direction::Up,
-            SPRITE_WIDTH_PLAYER,
-        ),
-        down_frames: generate_animation(
-            player_texture_idx,
-            player_top_left,
-            Direction::Down,
-            SPRITE_WIDTH_PLAYER,
-        ),
-        left_frames: generate_animation(
-            player_texture_idx,
-            player_top_left,
-            Direction::Left,
-            SPRITE_WIDTH_PLAYER,
-        ),
-        right_frames: generate_animation(
-            player_texture_idx,
-            player_top_left,
-            Direction::Right,
-            SPRITE_WIDTH_PLAYER,
-        ),
-    };
+    let player_animations = world.read_resource::<SpriteSheets>().movement_animation("player")?;
+    let (width, height) = player_animations.down.frames[0].region.size();
 
     world
         .create_entity()
@@ -160,110 +119,120 @@ pub fn add_player(world: &mut World) -> Result<(), String> {
             speed: 0 as i32,
             direction: VecDeque::new(),
         })
-        .with(CollisionBox {
-            width: SPRITE_WIDTH_PLAYER as u32,
-            height: SPRITE_HEIGHT_PLAYER as u32,
-        })
+        .with(CollisionBox { width, height })
         .with(Playable)
         .with(FlagForMovement{moving: false, new_pos: Position(Point::new( 0,  0))})
-        .with(player_animations.down_frames[0])
+        .with(player_animations.down.frames[0])
         .with(player_animations)
         .with(Facing::default())
         .with(InteractionZone::default())
+        .with(Inventory::new(PLAYER_INVENTORY_SLOTS))
+        .with(Equipment::default())
+        .with(Health::new(PLAYER_HEALTH))
+        .with(PLAYER_STATS)
         .build();
 
     Ok(())
 }
 
+// Named, so save games can find it again.
 pub fn add_reaper(world: &mut World) -> Result<(), String> {
-    let reaper_texture_idx = 1;
-    let reaper_top_left = Rect::new(
-        0,
-        0,
-        SPRITE_WIDTH_REAPER as u32,
-        SPRITE_HEIGHT_REAPER as u32,
-    );
-    let reaper_animations = MovementAnimation {
-        current_frame: 0,
-        up_frames: generate_animation(
-            reaper_texture_idx,
-            reaper_top_left,
-            Direction::Up,
-            SPRITE_WIDTH_REAPER,
-        ),
-        right_frames: generate_animation(
-            reaper_texture_idx,
-            reaper_top_left,
-            Direction::Right,
-            SPRITE_WIDTH_REAPER,
-        ),
-        down_frames: generate_animation(
-            reaper_texture_idx,
-            reaper_top_left,
-            Direction::Down,
-            SPRITE_WIDTH_REAPER,
-        ),
-        left_frames: generate_animation(
-            reaper_texture_idx,
-            reaper_top_left,
-            Direction::Left,
-            SPRITE_WIDTH_REAPER,
-        ),
-    };
+    let reaper = spawn_npc(world, 50, 50, "assets/test_dialogue.txt", true, "reaper")?;
+    world.write_storage::<Name>().insert(reaper, Name("reaper".to_string())).ok();
+    Ok(())
+}
+
+// `sheet` is the sprite sheet to look like, which needs the walk clips.
+pub fn spawn_npc(world: &mut World, x: i32, y: i32, dialogue_file: &str, wanders: bool, sheet: &str) -> Result<Entity, String> {
+    let npc_animations = world.read_resource::<SpriteSheets>().movement_animation(sheet)?;
+    let (width, height) = npc_animations.down.frames[0].region.size();
     let starting_velocity_npc: VecDeque<Direction> = VecDeque::new();
 
     let dialogue = Dialogue {
         sprite : Sprite {
-            spritesheet: 4,
+            spritesheet: texture(world, "dialogue_small")?,
             region: Rect::new(0, 0, 800, 200)
         },
-        dialogue_file: "assets/test_dialogue.txt".to_string(),
+        dialogue_file: dialogue_file.to_string(),
         show: false,
     };
 
-    world
+    let mut npc = world
         .create_entity()
-        .with(Position(Point::new(50, 50)))
-        .with(NPCWalker)
+        .with(Position(Point::new(x, y)))
         .with(Velocity {
             speed: 0,
             direction: starting_velocity_npc,
         })
-        .with(reaper_animations.down_frames[0])
+        .with(npc_animations.down.frames[0])
         .with(Interactable{
             interaction_type: InteractableType::Character,
             interactions: 0,
             max_interactions: 0,
         })
         .with(Unplayable)
-        .with(CollisionBox {
-            width: SPRITE_WIDTH_REAPER as u32,
-            height: SPRITE_HEIGHT_REAPER as u32,
-        })
-        .with(reaper_animations.clone())
-        .with(dialogue)
-        .build();
+        .with(CollisionBox { width, height })
+        .with(FlagForMovement{moving: false, new_pos: Position(Point::new(x, y))})
+        .with(npc_animations)
+        .with(dialogue);
+    if wanders {
+        npc = npc.with(NPCWalker);
+    }
+    let entity = npc.build();
 
-    Ok(())
+    Ok(entity)
 }
 
-pub fn spawn_fruit(world: &mut World, x: i32, y: i32) -> Result<(), String> {
-    let spritesheet = 2;
+// Like an NPC, but it hunts the player instead of talking. `sheet` needs the walk clips.
+pub fn spawn_enemy(world: &mut World, x: i32, y: i32, wanders: bool, sheet: &str) -> Result<Entity, String> {
+    let enemy_animations = world.read_resource::<SpriteSheets>().movement_animation(sheet)?;
+    let (width, height) = enemy_animations.down.frames[0].region.size();
 
-    let mut r = thread_rng();
-    let row = r.gen_range(0, 7);
-    let col = r.gen_range(0, 7);
+    let mut enemy = world
+        .create_entity()
+        .with(Position(Point::new(x, y)))
+        .with(Velocity::default())
+        .with(enemy_animations.down.frames[0])
+        .with(Unplayable)
+        .with(CollisionBox { width, height })
+        .with(FlagForMovement{moving: false, new_pos: Position(Point::new(x, y))})
+        .with(enemy_animations)
+        .with(Health::new(ENEMY_HEALTH))
+        .with(Hostile {
+            home: Point::new(x, y),
+            sight: ENEMY_SIGHT,
+            reach: ENEMY_REACH,
+            damage: ENEMY_DAMAGE,
+            speed: ENEMY_SPEED,
+            state: HostileState::Idle,
+            cooldown_ms: 0.0,
+        });
+    if wanders {
+        enemy = enemy.with(NPCWalker);
+    }
+    Ok(enemy.build())
+}
 
-    let region = Rect::new(
-        row * SPRITE_HEIGHT_FRUIT,
-        col * SPRITE_WIDTH_FRUIT,
-        SPRITE_WIDTH_FRUIT as u32,
-        SPRITE_HEIGHT_FRUIT as u32,
-    );
+// Fights alongside the player in battles from now on.
+pub fn join_party(world: &mut World, entity: Entity) {
+    world.write_storage::<PartyMember>().insert(entity, PartyMember).ok();
+    if !world.read_storage::<Health>().contains(entity) {
+        world.write_storage::<Health>().insert(entity, Health::new(PARTY_MEMBER_HEALTH)).ok();
+    }
+    world.write_storage::<Stats>().insert(entity, PARTY_MEMBER_STATS).ok();
+}
 
-    let fruit_sprite = Sprite{spritesheet, region};
+pub fn spawn_fruit(world: &mut World, x: i32, y: i32) -> Result<Entity, String> {
+    let (item, fruit_sprite) = {
+        let item_database = world.read_resource::<items::ItemDatabase>();
+        let food: Vec<&items::ItemDefinition> = item_database.in_category("food").collect();
+        let mut r = thread_rng();
+        let fruit = food.get(r.gen_range(0, food.len().max(1)))
+            .ok_or_else(|| "There are no food items to spawn".to_string())?;
+        (fruit.id.clone(), fruit.icon)
+    };
 
-    world
+    let entity = world
         .create_entity()
         .with(Position(Point::new(x, y)))
         .with(CollisionBox {
@@ -271,57 +240,236 @@ pub fn spawn_fruit(world: &mut World, x: i32, y: i32) -> Result<(), String> {
             height: SPRITE_HEIGHT_FRUIT as u32,
         })
         .with(Unplayable)
-        .with(Collectible)
+        .with(Collectible { item })
         .with(fruit_sprite)
         .build();
 
-    Ok(())
+    Ok(entity)
 }
 
-pub fn spawn_chest(world: &mut World, x: i32, y: i32) -> Result<(), String> {
-    let spritesheet = 3;
+// A specific item lying on the ground, picked up by walking over it.
+pub fn spawn_collectible(world: &mut World, x: i32, y: i32, item: &str) -> Result<Entity, String> {
+    let icon = world.read_resource::<items::ItemDatabase>().get(item)
+        .map(|item| item.icon)
+        .ok_or_else(|| format!("Unknown item \"{}\"", item))?;
+    let entity = world
+        .create_entity()
+        .with(Position(Point::new(x, y)))
+        .with(CollisionBox {
+            width: icon.region.width(),
+            height: icon.region.height(),
+        })
+        .with(Unplayable)
+        .with(Collectible { item: item.to_string() })
+        .with(icon)
+        .build();
+    Ok(entity)
+}
 
-    let chest_frames = generate_animation_chest(spritesheet);
-    let chest_animation = EntityAnimation{
-        current_frame: 0,
-        frames: chest_frames,
-    };
+pub fn spawn_chest(world: &mut World, x: i32, y: i32, loot: LootTable, links: Links) -> Result<Entity, String> {
+    let chest_animation = world.read_resource::<SpriteSheets>().entity_animation("chest", "open")?;
+    let (width, height) = chest_animation.sprite().region.size();
     let i = Interactable {
         interactions: 0,
         max_interactions: 1,
         interaction_type: InteractableType::Chest
     };
-    world
+    let entity = world
         .create_entity()
         .with(Position(Point::new(x, y)))
-        .with(CollisionBox {
-            width: SPRITE_WIDTH_CHEST as u32,
-            height: SPRITE_HEIGHT_CHEST as u32,
-        })
+        .with(CollisionBox { width, height })
         .with(Unplayable)
         .with(i)
+        .with(loot)
+        .with(links)
         .with(chest_animation.clone())
-        .with(chest_animation.frames[0])
+        .with(chest_animation.sprite())
         .build();
-    Ok(())
+    Ok(entity)
+}
+
+// A crate is smashed open the first time it is used, hands out its loot and disappears.
+pub fn spawn_crate(world: &mut World, x: i32, y: i32, loot: LootTable) -> Result<Entity, String> {
+    let crate_animation = world.read_resource::<SpriteSheets>().entity_animation(CONTAINERS_SHEET, "crate")?;
+    let (width, height) = crate_animation.sprite().region.size();
+    let entity = world
+        .create_entity()
+        .with(Position(Point::new(x, y)))
+        .with(CollisionBox { width, height })
+        .with(Unplayable)
+        .with(Interactable {
+            interactions: 0,
+            max_interactions: 1,
+            interaction_type: InteractableType::DestroyedOnUse,
+        })
+        .with(loot)
+        .with(crate_animation.sprite())
+        .with(crate_animation)
+        .build();
+    Ok(entity)
+}
+
+pub fn spawn_lever(world: &mut World, x: i32, y: i32, links: Links, variable: Option<&str>) -> Result<Entity, String> {
+    let switch = {
+        let sheets = world.read_resource::<SpriteSheets>();
+        Switch {
+            on: false,
+            off_sprite: sheets.sprite(CONTAINERS_SHEET, "lever_off")?,
+            on_sprite: sheets.sprite(CONTAINERS_SHEET, "lever_on")?,
+        }
+    };
+    let (width, height) = switch.off_sprite.region.size();
+    let entity = world
+        .create_entity()
+        .with(Position(Point::new(x, y)))
+        .with(CollisionBox { width, height })
+        .with(Unplayable)
+        .with(Interactable {
+            interactions: 0,
+            max_interactions: 0,
+            interaction_type: InteractableType::Lever,
+        })
+        .with(Lever {
+            on: false,
+            variable: variable.map(String::from),
+        })
+        .with(links)
+        .with(switch.sprite())
+        .with(switch)
+        .build();
+    Ok(entity)
+}
+
+pub fn spawn_door(world: &mut World, x: i32, y: i32, name: &str) -> Result<Entity, String> {
+    let (closed_sprite, open_sprite) = {
+        let sheets = world.read_resource::<SpriteSheets>();
+        (sheets.sprite(CONTAINERS_SHEET, "door_closed")?, sheets.sprite(CONTAINERS_SHEET, "door_open")?)
+    };
+    let (width, height) = closed_sprite.region.size();
+    let door = Door {
+        open: false,
+        closed_sprite,
+        open_sprite,
+        collision: CollisionBox { width, height },
+    };
+    let entity = world
+        .create_entity()
+        .with(Position(Point::new(x, y)))
+        .with(door.collision.clone())
+        .with(Unplayable)
+        .with(Name(name.to_string()))
+        .with(door.sprite())
+        .with(door)
+        .build();
+    Ok(entity)
+}
+
+// Plates aren't Unplayable, so they don't block the way.
+pub fn spawn_pressure_plate(world: &mut World, x: i32, y: i32, links: Links) -> Result<Entity, String> {
+    let switch = {
+        let sheets = world.read_resource::<SpriteSheets>();
+        Switch {
+            on: false,
+            off_sprite: sheets.sprite(CONTAINERS_SHEET, "plate_up")?,
+            on_sprite: sheets.sprite(CONTAINERS_SHEET, "plate_down")?,
+        }
+    };
+    let (width, height) = switch.off_sprite.region.size();
+    let entity = world
+        .create_entity()
+        .with(Position(Point::new(x, y)))
+        .with(CollisionBox { width, height })
+        .with(PressurePlate::default())
+        .with(links)
+        .with(switch.sprite())
+        .with(switch)
+        .build();
+    Ok(entity)
+}
+
+pub fn spawn_pickup(world: &mut World, x: i32, y: i32, item: &str, count: u32) -> Result<Entity, String> {
+    let icon = {
+        let item_database = world.read_resource::<items::ItemDatabase>();
+        item_database.get(item)
+            .ok_or_else(|| format!("Can't spawn unknown item \"{}\"", item))?
+            .icon
+    };
+    let entity = world
+        .create_entity()
+        .with(Position(Point::new(x, y)))
+        .with(CollisionBox {
+            width: icon.region.width(),
+            height: icon.region.height(),
+        })
+        .with(Unplayable)
+        .with(Interactable {
+            interactions: 0,
+            max_interactions: 0,
+            interaction_type: InteractableType::Pickup,
+        })
+        .with(Pickup {
+            item: item.to_string(),
+            count,
+        })
+        .with(icon)
+        .build();
+    Ok(entity)
+}
+
+// Puts the map's tiles in the world and returns what it places. Maps from Tiled come with their
+// entities, the plain text ones only have tiles.
+pub fn load_map(world: &mut World, path: &str) -> Result<Vec<level::Placement>, String> {
+    let (map, placements) = {
+        let textures = world.read_resource::<TextureRegistry>();
+        if path.ends_with(".tmj") || path.ends_with(".json") {
+            tiled::load(path, &textures)?
+        } else {
+            (tilemap::TileMap::load(path, &textures)?, Vec::new())
+        }
+    };
+    world.insert(map);
+    Ok(placements)
+}
+
+// The handle of a texture from the manifest.
+fn texture(world: &World, name: &str) -> Result<TextureHandle, String> {
+    world.read_resource::<TextureRegistry>().handle(name)
+}
+
+// Has no sprite or collision box, it only marks where items appear when it's signalled.
+pub fn spawn_item_spawner(world: &mut World, x: i32, y: i32, name: &str, item: &str) -> Result<Entity, String> {
+    if world.read_resource::<items::ItemDatabase>().get(item).is_none() {
+        return Err(format!("Spawner \"{}\" has an unknown item \"{}\"", name, item));
+    }
+    let entity = world
+        .create_entity()
+        .with(Position(Point::new(x, y)))
+        .with(Name(name.to_string()))
+        .with(ItemSpawner { item: item.to_string() })
+        .build();
+    Ok(entity)
 }
 
-#[allow(dead_code)]
 pub fn load_dialogue(world: &mut World) -> Result<(), String> {
-    let spritesheet = vec![4, 5, 6];
     let small_dialogue_sprite = Sprite {
         region: Rect::new(0, 0, 800, 200),
-        spritesheet: spritesheet[0],
+        spritesheet: texture(world, "dialogue_small")?,
     };
     let medium_dialogue_sprite = Sprite {
         region: Rect::new(0, 0, 800, 400),
-        spritesheet: spritesheet[1],
+        spritesheet: texture(world, "dialogue_medium")?,
     };
     let large_dialogue_sprite = Sprite {
         region: Rect::new(0, 0, 800, 600),
-        spritesheet: spritesheet[2],
+        spritesheet: texture(world, "dialogue_large")?,
     };
 
+    world.insert(DialogueBoxes {
+        small: small_dialogue_sprite,
+        medium: medium_dialogue_sprite,
+        large: large_dialogue_sprite,
+    });
+
     Ok(())
 }
 
@@ -341,14 +489,24 @@ pub fn main() -> Result<(), String> {
         .expect("Could not make a canvas");
 
     let texture_creator = canvas.texture_creator();
+    let ttf_context = sdl2::ttf::init().map_err(|e| e.to_string())?;
+    let mut fonts = fonts::FontManager::new(&ttf_context, &texture_creator);
+    fonts.register("serif", "assets/fonts/DejaVuSerif.ttf");
 
     let mut dispatcher = DispatcherBuilder::new()
         .with(keyboard::Keyboard, "Keyboard", &[])
         .with(physics::Physics, "Physics", &["Keyboard"])
         .with(animator::Animator, "Animator", &["Keyboard", "Physics"])
         .with(randomwalker::RandomWalker, "RandomWalker", &["Physics"])
+        .with(hostiles::Hostiles, "Hostiles", &["RandomWalker", "Physics"])
         .with(collectibles::Collectibles, "Collectibles",&["Physics", "Animator", "Keyboard"])
         .with(update_interaction::IZUpdater, "Interaction Zone", &["Physics", "Keyboard"])
+        .with(typewriter::Typewriter, "Typewriter", &["Keyboard"])
+        .with(pressure_plates::PressurePlates, "PressurePlates", &["Physics"])
+        .with(switches::Switches, "Switches", &["Keyboard", "Animator", "PressurePlates"])
+        .with(combat::Combat, "Combat", &["Keyboard", "Physics", "Animator"])
+        .with(health::Vitals, "Vitals", &["Combat", "Hostiles"])
+        .with(battle::Battles, "Battles", &["Keyboard", "Hostiles"])
         .build();
 
     let mut world_clock: Option<Instant> = None;
@@ -363,43 +521,58 @@ pub fn main() -> Result<(), String> {
     let mut draw_bounding_box = true;
     let mut draw_interaction_zone = true;
     let mut thegame = Gamestate::Running;
-    let mut dialogue_list: VecDeque<Dialogue_Single_item> = VecDeque::new();
-    let mut previous_dialogue_text = Dialogue_Helper{text: String::from(" "), width: 0, height: 0};
+    let active_dialogue = dialogue::ActiveDialogue::default();
+    let previous_dialogue_text = Dialogue_Helper::default();
 
     world.insert(movement_command);
     world.insert(world_clock);
     world.insert(player_command);
     world.insert(thegame);
-    world.insert(dialogue_list);
+    world.insert(active_dialogue);
+    world.insert(variables::WorldVariables::default());
+    world.insert(DialogueSettings::default());
+    world.insert(Notifications::default());
+    world.insert(Signals::default());
+    world.insert(menu::MenuState::default());
     world.insert(previous_dialogue_text);
+    world.insert(DeltaTime::default());
+    world.insert(AnimationEvents::default());
+    world.insert(battle::Battle::default());
     world.register::<EntityAnimation>();
+    world.register::<level::Placed>();
+    world.register::<level::PlayerStart>();
 
 
-    let textures = [
-        texture_creator.load_texture("assets/bardo.png")?,
-        texture_creator.load_texture("assets/reaper.png")?,
-        texture_creator.load_texture("assets/food.png")?,
-        texture_creator.load_texture("assets/chest.png")?,
-        texture_creator.load_texture("assets/dialogue_800x200.png")?,
-        texture_creator.load_texture("assets/dialogue_800x400.png")?,
-        texture_creator.load_texture("assets/dialogue_800x600.png")?,
-    ];
-
-    let mut texture_idx = HashMap::new(); // maybe unused? 
-    texture_idx.insert("player".to_string(), 0);
-    texture_idx.insert("reaper".to_string(), 1);
-    texture_idx.insert("food".to_string(), 2);
-    texture_idx.insert("chest".to_string(), 3);
-    texture_idx.insert("dialogue_small".to_string(), 4);
-    texture_idx.insert("dialogue_medium".to_string(), 5);
-    texture_idx.insert("dialogue_large".to_string(), 6);
-
-    let mut spawn_index = Spawner::Chests;
+    let texture_registry = TextureRegistry::load(TEXTURE_MANIFEST)?;
+    let textures = texture_registry.paths().iter()
+        .map(|path| texture_creator.load_texture(path))
+        .collect::<Result<Vec<_>, String>>()?;
+    let (speakers, item_database) = {
+        let speakers = dialogue::Speakers::load("assets/speakers.txt", &texture_registry)?;
+        (speakers, items::ItemDatabase::load("assets/items.txt", &texture_registry)?)
+    };
+    let sprite_sheets = SpriteSheets::load(SPRITE_SHEETS, &texture_registry)?;
+    world.insert(foes::FoeDatabase::load("assets/foes.txt", &sprite_sheets)?);
+    world.insert(levels::LevelTable::load("assets/levels.txt")?);
+    world.insert(sprite_sheets);
+    world.insert(texture_registry);
+    world.insert(speakers);
+    world.insert(item_database);
 
     add_player(&mut world)?;
     add_reaper(&mut world)?;
+    load_dialogue(&mut world)?;
+    let map_placements = load_map(&mut world, START_MAP)?;
+    let placements = if level::exists(START_LEVEL) { level::load(START_LEVEL)? } else { map_placements };
+    level::spawn_all(&mut world, &placements)?;
+    level::place_player(&mut world);
+    collision_editor::load(&mut world, START_COLLISION)?;
+    let mut collision_editor = collision_editor::CollisionEditor::default();
+    let mut level_editor = level_editor::LevelEditor::default();
+    let mut save_slot = 1;
 
     world_clock = Some(Instant::now());
+    let mut last_tick = Instant::now();
 
     // Gradient test
     let mut background_texture = texture_creator.
@@ -444,12 +617,31 @@ pub fn main() -> Result<(), String> {
         let mut player_command: Option<PlayerCommands> = None;
         //println!("Gamestate before catching events: {:?}", thegame);
         for event in event_pump.poll_iter() {
+            let editing_collision = *world.read_resource::<Gamestate>() == Gamestate::CollisionEditor;
+            let editing_level = *world.read_resource::<Gamestate>() == Gamestate::LevelEditor;
+            let (w, h) = canvas.output_size()?;
+            let (w, h) = (w as i32, h as i32);
             match event {
-                Event::Quit { .. } | Event::KeyDown {
+                Event::Quit { .. } => break 'running,
+                // Escape cancels typing in the level editor instead.
+                Event::KeyDown {
                     keycode: Some(Keycode::Escape),
                     repeat: false,
                     ..
-                } => break 'running,
+                } if !level_editor.typing() => break 'running,
+
+                Event::KeyDown {
+                    keycode: Some(key),
+                    keymod,
+                    ..
+                } if editing_level && !matches!(key, Keycode::F1 | Keycode::F2 | Keycode::F4) => {
+                    let ctrl = keymod.intersects(Mod::LCTRLMOD | Mod::RCTRLMOD);
+                    if let Err(e) = level_editor.key_down(&mut world, key, ctrl, START_LEVEL) {
+                        println!("{}", e);
+                        world.write_resource::<Notifications>().push(e);
+                    }
+                },
+                Event::TextInput { text, .. } if editing_level => level_editor.type_text(&text),
 
                 Event::KeyDown {
                     keycode: Some(key),
@@ -462,13 +654,70 @@ pub fn main() -> Result<(), String> {
                         Keycode::Right => movement_command.push_back(Some(MovementCommand::Move(Direction::Right))),
                         Keycode::Left => movement_command.push_back(Some(MovementCommand::Move(Direction::Left))),
                         Keycode::Z => {player_command = Some(PlayerCommands::Interact)},
+                        Keycode::X => {player_command = Some(PlayerCommands::Cancel)},
+                        Keycode::I => {player_command = Some(PlayerCommands::Menu)},
+                        Keycode::C => {player_command = Some(PlayerCommands::Attack)},
+                        Keycode::S if !editing_collision => {player_command = Some(PlayerCommands::Status)},
 
 
                         // Debugging
                         Keycode::F1 => draw_bounding_box = !draw_bounding_box,
                         Keycode::F2 => draw_interaction_zone = !draw_interaction_zone,
-                        Keycode::Num1 => spawn_index = Spawner::Chests,
-                        Keycode::Num2 => spawn_index = Spawner::Fruit,
+                        Keycode::F3 => {
+                            let gamestate = *world.read_resource::<Gamestate>();
+                            match gamestate {
+                                Gamestate::Running => *world.write_resource() = Gamestate::CollisionEditor,
+                                Gamestate::CollisionEditor => *world.write_resource() = Gamestate::Running,
+                                _ => {},
+                            }
+                        },
+                        Keycode::F4 => {
+                            let gamestate = *world.read_resource::<Gamestate>();
+                            match gamestate {
+                                Gamestate::Running => *world.write_resource() = Gamestate::LevelEditor,
+                                Gamestate::LevelEditor => *world.write_resource() = Gamestate::Running,
+                                _ => {},
+                            }
+                        },
+                        // Saving
+                        Keycode::F5 | Keycode::F9 | Keycode::F6 if !matches!(*world.read_resource::<Gamestate>(), Gamestate::Running | Gamestate::Pause) => {
+                            world.write_resource::<Notifications>().push("Can only save and load while playing".to_string());
+                        },
+                        Keycode::F5 => {
+                            let message = match savegame::save(&world, save_slot) {
+                                Ok(()) => format!("Saved to slot {}", save_slot),
+                                Err(e) => e,
+                            };
+                            world.write_resource::<Notifications>().push(message);
+                        },
+                        Keycode::F6 => {
+                            save_slot = save_slot % savegame::SAVE_SLOTS + 1;
+                            let used = if savegame::slot_exists(save_slot) { "" } else { " (empty)" };
+                            world.write_resource::<Notifications>().push(format!("Save slot {}{}", save_slot, used));
+                        },
+                        Keycode::F9 => {
+                            let message = match savegame::load(&mut world, save_slot) {
+                                Ok(()) => format!("Loaded slot {}", save_slot),
+                                Err(e) => e,
+                            };
+                            world.write_resource::<Notifications>().push(message);
+                        },
+                        Keycode::S if editing_collision => {
+                            let message = match collision_editor::save(&world, START_COLLISION) {
+                                Ok(()) => format!("Saved {}", START_COLLISION),
+                                Err(e) => e,
+                            };
+                            world.write_resource::<Notifications>().push(message);
+                        },
+                        Keycode::L if editing_collision => {
+                            collision_editor.selected = None;
+                            let message = match collision_editor::load(&mut world, START_COLLISION) {
+                                Ok(()) => format!("Loaded {}", START_COLLISION),
+                                Err(e) => e,
+                            };
+                            world.write_resource::<Notifications>().push(message);
+                        },
+                        Keycode::Delete | Keycode::Backspace if editing_collision => collision_editor.delete_selected(&mut world),
                         Keycode::Kp0 => {
                             thegame = Gamestate::Pause;
                             *world.write_resource() = thegame;
@@ -497,16 +746,40 @@ pub fn main() -> Result<(), String> {
                 }
 
 
-                Event::MouseButtonDown{x, y, ..} => {
-                    let (w, h) = canvas.output_size()?;
-                    let w = w as i32;
-                    let h = h as i32;
-                    match spawn_index {
-                        Spawner::Chests => spawn_chest(&mut world, x-w/2, y-h/2)?,
-                        Spawner::Fruit => spawn_fruit(&mut world, x-w/2, y-h/2)?,
+                Event::MouseButtonDown{x, y, mouse_btn, ..} if editing_collision => {
+                    match mouse_btn {
+                        MouseButton::Left => collision_editor.mouse_down(&mut world, Point::new(x-w/2, y-h/2)),
+                        MouseButton::Right => collision_editor.delete_at(&mut world, Point::new(x-w/2, y-h/2)),
+                        _ => {},
                     }
+                },
+                Event::MouseMotion{x, y, ..} if editing_collision => {
+                    collision_editor.mouse_moved(&mut world, Point::new(x-w/2, y-h/2));
+                },
+                Event::MouseButtonUp{mouse_btn: MouseButton::Left, ..} if editing_collision => {
+                    collision_editor.mouse_up(&mut world);
+                },
 
+                Event::MouseButtonDown{x, y, mouse_btn, ..} if editing_level => {
+                    let point = Point::new(x-w/2, y-h/2);
+                    match mouse_btn {
+                        MouseButton::Left => {
+                            if let Err(e) = level_editor.mouse_down(&mut world, point) {
+                                println!("{}", e);
+                                world.write_resource::<Notifications>().push(e);
+                            }
+                        },
+                        MouseButton::Right => level_editor.delete_at(&mut world, point),
+                        _ => {},
+                    }
+                },
+                Event::MouseMotion{x, y, ..} if editing_level => {
+                    level_editor.mouse_moved(&mut world, Point::new(x-w/2, y-h/2));
                 },
+                Event::MouseButtonUp{mouse_btn: MouseButton::Left, ..} if editing_level => {
+                    level_editor.mouse_up();
+                },
+                Event::MouseWheel{y, ..} if editing_level => level_editor.scroll_palette(y),
 
                 _ => {}
             }
@@ -514,7 +787,22 @@ pub fn main() -> Result<(), String> {
         //println!("Gamestate after catching events: {:?}", thegame);
         *world.write_resource() = movement_command;
         *world.write_resource() = world_clock;
+        // Interact after a game over goes back to the last save, or the start without one.
+        if *world.read_resource::<Gamestate>() == Gamestate::GameOver && matches!(player_command, Some(PlayerCommands::Interact)) {
+            player_command = None;
+            if savegame::slot_exists(save_slot) {
+                if let Err(e) = savegame::load(&mut world, save_slot) {
+                    world.write_resource::<Notifications>().push(e);
+                    health::revive(&mut world);
+                }
+            } else {
+                health::revive(&mut world);
+            }
+        }
         *world.write_resource() = player_command;
+        let now = Instant::now();
+        *world.write_resource() = DeltaTime((now - last_tick).min(MAX_TICK));
+        last_tick = now;
         //*world.write_resource() = thegame;
 
         // Update
@@ -525,10 +813,15 @@ pub fn main() -> Result<(), String> {
         renderer::render(&mut canvas,
             color,
             &textures,
+            &mut fonts,
             world.system_data(),
-            draw_bounding_box,
-            draw_interaction_zone,
             &background_texture,
+            renderer::Overlays {
+                bounding_boxes: draw_bounding_box,
+                interaction_zones: draw_interaction_zone,
+                collision_editor: &collision_editor,
+                level_editor: &level_editor,
+            },
         )?;
         // Time Management
         ::std::thread::sleep(Duration::new(0, 1_000_000_000u32 / 20));
@@ -0,0 +1,175 @@
+use crate::components::*;
+use crate::items::ItemDatabase;
+use crate::variables::WorldVariables;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum MenuAction {
+    #[default]
+    Use,
+    Equip,
+    Drop,
+}
+
+impl MenuAction {
+    pub const ALL: [MenuAction; 3] = [MenuAction::Use, MenuAction::Equip, MenuAction::Drop];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            MenuAction::Use => "Use",
+            MenuAction::Equip => "Equip",
+            MenuAction::Drop => "Drop",
+        }
+    }
+
+    fn index(self) -> usize {
+        Self::ALL.iter().position(|&a| a == self).unwrap_or(0)
+    }
+}
+
+// Which entry and action are highlighted in the inventory menu, kept while Gamestate::Menu is active.
+#[derive(Debug, Default)]
+pub struct MenuState {
+    pub selected: usize,
+    pub action: MenuAction,
+}
+
+// One row of the menu: a stack in the inventory, or an item that is equipped.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MenuEntry {
+    Stack { item: String, count: u32 },
+    Equipped { slot: String, item: String },
+}
+
+impl MenuEntry {
+    pub fn item(&self) -> &str {
+        match self {
+            MenuEntry::Stack { item, .. } => item,
+            MenuEntry::Equipped { item, .. } => item,
+        }
+    }
+}
+
+pub fn entries(inventory: &Inventory, equipment: Option<&Equipment>) -> Vec<MenuEntry> {
+    let stacks = inventory.slots.iter().map(|stack| MenuEntry::Stack {
+        item: stack.item.clone(),
+        count: stack.count,
+    });
+    let equipped = equipment.into_iter()
+        .flat_map(|equipment| equipment.slots.iter())
+        .map(|(slot, item)| MenuEntry::Equipped { slot: slot.clone(), item: item.clone() });
+    stacks.chain(equipped).collect()
+}
+
+impl MenuState {
+    pub fn select_previous(&mut self) {
+        self.selected = self.selected.saturating_sub(1);
+    }
+
+    pub fn select_next(&mut self, entries: usize) {
+        if self.selected + 1 < entries {
+            self.selected += 1;
+        }
+    }
+
+    pub fn previous_action(&mut self) {
+        let i = self.action.index();
+        self.action = MenuAction::ALL[(i + MenuAction::ALL.len() - 1) % MenuAction::ALL.len()];
+    }
+
+    pub fn next_action(&mut self) {
+        let i = self.action.index();
+        self.action = MenuAction::ALL[(i + 1) % MenuAction::ALL.len()];
+    }
+
+    fn clamp(&mut self, entries: usize) {
+        self.selected = self.selected.min(entries.saturating_sub(1));
+    }
+
+    // Carries out the highlighted action on the highlighted entry. Returns the id of an item the
+    // player dropped, so it can be put back into the world.
+    pub fn perform(
+        &mut self,
+        inventory: &mut Inventory,
+        mut equipment: Option<&mut Equipment>,
+        health: Option<&mut Health>,
+        database: &ItemDatabase,
+        variables: &mut WorldVariables,
+        notifications: &mut Notifications,
+    ) -> Option<String> {
+        let entry = entries(inventory, equipment.as_deref()).get(self.selected)?.clone();
+        let item = match database.get(entry.item()) {
+            Some(item) => item,
+            None => {
+                println!("Unknown item \"{}\" in the inventory", entry.item());
+                return None;
+            },
+        };
+
+        let mut dropped = None;
+        match (self.action, &entry) {
+            (_, MenuEntry::Equipped { slot, .. }) => {
+                // Anything equipped can only be taken off.
+                if self.action != MenuAction::Equip {
+                    notifications.push(format!("Unequip the {} first.", item.name));
+                } else if let Some(equipment) = equipment.as_deref_mut() {
+                    if inventory.room_for(item) == 0 {
+                        notifications.push(format!("No room to unequip the {}.", item.name));
+                    } else {
+                        equipment.slots.remove(slot);
+                        inventory.add(item, 1);
+                        notifications.push(format!("You unequipped the {}.", item.name));
+                    }
+                }
+            },
+            (MenuAction::Use, MenuEntry::Stack { .. }) => {
+                // Something that only heals isn't wasted on someone who isn't hurt.
+                let hurt = health.as_ref().is_some_and(|hp| hp.current < hp.max);
+                let heals = item.heal.is_some() && health.is_some();
+                if item.on_use.is_none() && !heals {
+                    notifications.push(format!("The {} can't be used.", item.name));
+                } else if item.on_use.is_none() && !hurt {
+                    notifications.push("You are already at full health.".to_string());
+                } else {
+                    inventory.remove(&item.id, 1);
+                    if let Some(effect) = &item.on_use {
+                        effect.apply(variables);
+                    }
+                    if let (Some(amount), Some(hp)) = (item.heal, health) {
+                        hp.current = (hp.current + amount).min(hp.max);
+                    }
+                    notifications.push(format!("You used the {}.", item.name));
+                }
+            },
+            (MenuAction::Equip, MenuEntry::Stack { .. }) => match (&item.slot, equipment.as_deref_mut()) {
+                (Some(slot), Some(equipment)) => {
+                    // Whatever is in the slot goes back into the inventory. Taking the last of a stack
+                    // frees up its slot, otherwise there has to be room already.
+                    let previous = equipment.slots.get(slot).and_then(|id| database.get(id));
+                    let has_room = match previous {
+                        Some(previous) => inventory.count(&item.id) == 1 || inventory.room_for(previous) > 0,
+                        None => true,
+                    };
+                    if has_room {
+                        inventory.remove(&item.id, 1);
+                        if let Some(previous) = previous {
+                            inventory.add(previous, 1);
+                        }
+                        equipment.slots.insert(slot.clone(), item.id.clone());
+                        notifications.push(format!("You equipped the {}.", item.name));
+                    } else {
+                        notifications.push(format!("No room to swap out what is in the {} slot.", slot));
+                    }
+                },
+                _ => notifications.push(format!("The {} can't be equipped.", item.name)),
+            },
+            (MenuAction::Drop, MenuEntry::Stack { .. }) => {
+                inventory.remove(&item.id, 1);
+                notifications.push(format!("You dropped the {}.", item.name));
+                dropped = Some(item.id.clone());
+            },
+        }
+
+        self.clamp(entries(inventory, equipment.as_deref()).len());
+        dropped
+    }
+}
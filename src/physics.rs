@@ -1,6 +1,7 @@
-use specs::{WriteStorage, System, ReadStorage, join::Join};
+use specs::{WriteStorage, System, ReadStorage, ReadExpect, join::Join, Entities};
 use sdl2::rect::{Rect};
 use crate::components::*;
+use crate::tilemap::TileMap;
 
 pub struct Physics;
 
@@ -13,6 +14,9 @@ impl<'a> System<'a> for Physics {
         WriteStorage<'a, FlagForMovement>,
         ReadStorage<'a, Playable>,
         ReadStorage<'a, Unplayable>,
+        ReadExpect<'a, TileMap>,
+        ReadStorage<'a, Knockback>,
+        Entities<'a>,
     );
 
     fn run(
@@ -24,20 +28,33 @@ impl<'a> System<'a> for Physics {
             mut movementflags, 
             _playableflag, 
             unplayableflag,
+            tilemap,
+            knockback,
+            entities,
         ): Self::SystemData) {
 
         let mut new_pos = Position::default();
-        for (pos, vel, _, flag) in (&position, &velocity, &collisionbox, &mut movementflags).join() {
-            if !vel.direction.is_empty() { 
-                for (obj_pos, obj_col, _) in (&position, &collisionbox, &unplayableflag).join() {
+        for (entity, pos, vel, col, flag, knock) in (&entities, &position, &velocity, &collisionbox, &mut movementflags, (&knockback).maybe()).join() {
+            // Being knocked back takes over from walking.
+            let step = match knock {
+                Some(knock) => Some((knock.direction, knock.speed)),
+                None => vel.direction.front().map(|&dir| (dir, vel.speed)),
+            };
+            if let Some((dir, speed)) = step {
+                new_pos.0 = match dir {
+                    Direction::Right => (pos.0.offset(speed, 0)),
+                    Direction::Left => (pos.0.offset(-speed, 0)),
+                    Direction::Down => (pos.0.offset(0, speed)),
+                    Direction::Up => (pos.0.offset(0,-speed)),
+                };
+                flag.moving = true;
+                flag.new_pos = new_pos;
+                for (obj, obj_pos, obj_col, _) in (&entities, &position, &collisionbox, &unplayableflag).join() {
+                    // Nothing is in its own way.
+                    if obj == entity {
+                        continue;
+                    }
                     let obj_rect = Rect::from_center(obj_pos.0, obj_col.width, obj_col.height); 
-                    let dir = &vel.direction.front().unwrap(); 
-                    new_pos.0 = match dir {
-                        Direction::Right => (pos.0.offset(vel.speed, 0)),
-                        Direction::Left => (pos.0.offset(-vel.speed, 0)),
-                        Direction::Down => (pos.0.offset(0, vel.speed)),
-                        Direction::Up => (pos.0.offset(0,-vel.speed)),
-                    };
                     match obj_rect.intersect_line(pos.0, new_pos.0) {
                         Some(_) => {
                             //println!("Movement not ok! 💔");
@@ -51,6 +68,10 @@ impl<'a> System<'a> for Physics {
                         },
                     };
                 }
+                // Solid tiles of the map are in the way too.
+                if flag.moving && tilemap.blocks(Rect::from_center(flag.new_pos.0, col.width, col.height)) {
+                    flag.moving = false;
+                }
             }
         }
 
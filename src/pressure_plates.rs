@@ -0,0 +1,44 @@
+use specs::{System, ReadStorage, WriteStorage, WriteExpect, join::Join};
+use sdl2::rect::{Rect};
+use crate::components::*;
+
+pub struct PressurePlates;
+
+impl<'a> System<'a> for PressurePlates {
+
+    type SystemData = (
+        ReadStorage<'a, Position>,
+        ReadStorage<'a, CollisionBox>,
+        ReadStorage<'a, Velocity>,
+        WriteStorage<'a, PressurePlate>,
+        ReadStorage<'a, Links>,
+        WriteStorage<'a, Switch>,
+        WriteStorage<'a, Sprite>,
+        WriteExpect<'a, Signals>,
+    );
+
+    fn run(&mut self, (position, collisionbox, velocity, mut plate, links, mut switch, mut sprite, mut signals): Self::SystemData) {
+
+        for (plate_pos, plate_col, plate, links, switch, sprite) in (&position, &collisionbox, &mut plate, (&links).maybe(), (&mut switch).maybe(), (&mut sprite).maybe()).join() {
+            let plate_rect = Rect::from_center(plate_pos.0, plate_col.width, plate_col.height);
+
+            // Anything that walks around can press it, the player as well as NPCs.
+            let pressed = (&position, &collisionbox, &velocity).join()
+                .any(|(pos, col, _)| Rect::from_center(pos.0, col.width, col.height).has_intersection(plate_rect));
+            if pressed == plate.pressed {
+                continue;
+            }
+            plate.pressed = pressed;
+
+            if let Some(links) = links {
+                signals.send(links, pressed);
+            }
+            if let Some(switch) = switch {
+                switch.on = pressed;
+                if let Some(sprite) = sprite {
+                    *sprite = switch.sprite();
+                }
+            }
+        }
+    }
+}
@@ -25,6 +25,8 @@ impl<'a> System<'a> for RandomWalker {
         for (_, vel) in (&is_walker, &mut velocity).join() {
             if time.elapsed().as_millis() % 50 == 0 {
                 let sample: f64 = r.gen();
+                // Only the front direction is walked, so the new one replaces it. Now and then they stand still.
+                vel.direction.clear();
                 if sample < 0.9 {
                     let dir: Direction = r.gen();
                     vel.direction.push_back(dir);
@@ -1,12 +1,73 @@
 use specs::{ReadStorage, join::Join, ReadExpect};
+use crate::battle::{Battle, BattleCommand, Outcome, Phase, Side, battle_items};
 use crate::components::*;
-use sdl2::render::{WindowCanvas, Texture, TextureCreator, TextureQuery};
+use crate::dialogue::{ActiveDialogue, Speakers};
+use crate::fonts::{FontManager, TextStyle};
+use crate::items::ItemDatabase;
+use crate::menu::{MenuAction, MenuEntry, MenuState, entries};
+use crate::tilemap::{LayerKind, TileMap};
+use crate::collision_editor::{CollisionEditor, collider_rect, handle_rect};
+use crate::level::{KINDS, Placed};
+use crate::level_editor::{LevelEditor, placed_rect};
+use crate::levels::LevelTable;
+use sdl2::render::{BlendMode, WindowCanvas, Texture, TextureQuery};
 use sdl2::pixels::Color;
-use sdl2::{video::WindowContext, rect::{Point, Rect}};
-use std::collections::{VecDeque};
+use sdl2::ttf::FontStyle;
+use sdl2::rect::{Point, Rect};
 use shred::WriteExpect;
 
-const FONT_SIZE_DIALOGUE: u16 = 24;
+pub const DIALOGUE_TEXT: TextStyle = TextStyle {
+    font: "serif",
+    size: 24,
+    style: FontStyle::BOLD,
+    color: Color::RGB(255, 0, 0),
+};
+pub const NOTIFICATION_TEXT: TextStyle = TextStyle {
+    font: "serif",
+    size: 18,
+    style: FontStyle::NORMAL,
+    color: Color::RGB(255, 255, 255),
+};
+pub const MENU_TEXT: TextStyle = TextStyle {
+    font: "serif",
+    size: 20,
+    style: FontStyle::NORMAL,
+    color: Color::RGB(230, 230, 230),
+};
+pub const MENU_HIGHLIGHT_TEXT: TextStyle = TextStyle {
+    font: "serif",
+    size: 20,
+    style: FontStyle::BOLD,
+    color: Color::RGB(255, 220, 60),
+};
+pub const NAME_PLATE_TEXT: TextStyle = TextStyle {
+    font: "serif",
+    size: 20,
+    style: FontStyle::BOLD,
+    color: Color::RGB(255, 255, 255),
+};
+
+const DIALOGUE_MARGIN_X: i32 = 40;
+const DIALOGUE_MARGIN_Y: i32 = 30;
+const DIALOGUE_CHOICE_INDENT: i32 = 20;
+const PORTRAIT_SCALE: u32 = 2;
+const PORTRAIT_GAP: i32 = 20;
+const NAME_PLATE_PADDING: i32 = 8;
+const NOTIFICATION_MARGIN: i32 = 10;
+const MENU_PANEL: (i32, i32, u32, u32) = (100, 50, 600, 500);
+const MENU_PADDING: i32 = 20;
+const MENU_ROW_HEIGHT: i32 = 36;
+const MENU_ICON_SCALE: u32 = 2;
+const EDITOR_PANEL_WIDTH: u32 = 260;
+const EDITOR_ROW_HEIGHT: i32 = 22;
+const HEALTH_BAR_SIZE: (u32, u32) = (160, 14);
+// Bars over hurt characters are as wide as they are.
+const SMALL_HEALTH_BAR_HEIGHT: u32 = 4;
+// Something that can't be hurt for now blinks, this many milliseconds on and off.
+const BLINK_MS: u32 = 100;
+const BATTLE_SPRITE_SCALE: u32 = 2;
+const BATTLE_PANEL_HEIGHT: u32 = 170;
+const BATTLE_BAR_SIZE: (u32, u32) = (80, 8);
 
 pub type SystemData<'a> = (
     ReadStorage<'a, Position>,
@@ -14,10 +75,36 @@ pub type SystemData<'a> = (
     ReadStorage<'a, CollisionBox>,
     ReadStorage<'a, InteractionZone>,
     ReadExpect<'a, Gamestate>,
-    ReadExpect<'a, VecDeque<Dialogue_Single_item>>,
+    ReadExpect<'a, ActiveDialogue>,
     WriteExpect<'a, Dialogue_Helper>,
+    ReadExpect<'a, DialogueBoxes>,
+    ReadExpect<'a, Speakers>,
+    ReadExpect<'a, Notifications>,
+    ReadStorage<'a, KeyboardControlled>,
+    ReadStorage<'a, Inventory>,
+    ReadStorage<'a, Equipment>,
+    ReadExpect<'a, MenuState>,
+    ReadExpect<'a, ItemDatabase>,
+    ReadExpect<'a, TileMap>,
+    ReadStorage<'a, StaticCollider>,
+    ReadStorage<'a, Placed>,
+    ReadStorage<'a, Hitbox>,
+    ReadStorage<'a, Health>,
+    ReadExpect<'a, Battle>,
+    ReadStorage<'a, Stats>,
+    ReadStorage<'a, PartyMember>,
+    ReadStorage<'a, Name>,
+    ReadExpect<'a, LevelTable>,
 );
 
+// What is drawn over the game: the F1 and F2 debug outlines, and the editors while they are open.
+pub struct Overlays<'a> {
+    pub bounding_boxes: bool,
+    pub interaction_zones: bool,
+    pub collision_editor: &'a CollisionEditor,
+    pub level_editor: &'a LevelEditor,
+}
+
 pub fn update_canvas (
         pos: &Position, 
         sprite: &Sprite, 
@@ -30,45 +117,616 @@ pub fn update_canvas (
 
     let screen_rect = Rect::from_center(screen_coord, current_frame.width(), current_frame.height());
 
-    canvas.copy(&textures[sprite.spritesheet], current_frame, screen_rect)?;
+    canvas.copy(&textures[sprite.spritesheet.index()], current_frame, screen_rect)?;
     
     Ok(())
 }
 
-pub fn text_to_texture<'a>(texture_creator: &'a TextureCreator<WindowContext>, text: &str) -> Result<Texture<'a>, String> {
+// Draws text with its top left corner at (x, y) and returns its size, or None if nothing could be drawn.
+pub fn draw_text(
+    canvas: &mut WindowCanvas,
+    fonts: &mut FontManager,
+    style: &TextStyle,
+    text: &str,
+    x: i32,
+    y: i32,
+) -> Result<Option<(u32, u32)>, String> {
+    draw_text_prefix(canvas, fonts, style, text, text.chars().count(), x, y)
+}
 
-    // Load a font
-    let ttf_context = sdl2::ttf::init().map_err(|e| e.to_string())?;
-    let mut font = ttf_context.load_font("/usr/share/fonts/truetype/liberation/LiberationSerif-Regular.ttf", FONT_SIZE_DIALOGUE)?;
-    
-    font.set_style(sdl2::ttf::FontStyle::BOLD);
+// Like draw_text, but only shows the first `shown` characters. The whole text is rendered and cropped,
+// so a line that is being revealed doesn't need a new texture for every character.
+pub fn draw_text_prefix(
+    canvas: &mut WindowCanvas,
+    fonts: &mut FontManager,
+    style: &TextStyle,
+    text: &str,
+    shown: usize,
+    x: i32,
+    y: i32,
+) -> Result<Option<(u32, u32)>, String> {
+    let prefix: String = text.chars().take(shown).collect();
+    let width = match fonts.size_of(style, &prefix) {
+        Some((width, _)) if width > 0 => width,
+        _ => return Ok(None),
+    };
+    let texture = match fonts.text(style, text) {
+        Some(texture) => texture,
+        None => return Ok(None),
+    };
+    let TextureQuery { height, .. } = texture.query();
+    canvas.copy(texture, Rect::new(0, 0, width, height), Rect::new(x, y, width, height))?;
+    Ok(Some((width, height)))
+}
+
+fn render_health_bar(canvas: &mut WindowCanvas, bar: Rect, health: &Health) -> Result<(), String> {
+    let previous_color = canvas.draw_color();
+    canvas.set_draw_color(Color::RGB(40, 0, 0));
+    canvas.fill_rect(bar)?;
+    let filled = bar.width() * health.current / health.max.max(1);
+    if filled > 0 {
+        canvas.set_draw_color(Color::RGB(200, 30, 30));
+        canvas.fill_rect(Rect::new(bar.x(), bar.y(), filled, bar.height()))?;
+    }
+    canvas.set_draw_color(Color::RGB(255, 255, 255));
+    canvas.draw_rect(bar)?;
+    canvas.set_draw_color(previous_color);
+    Ok(())
+}
+
+fn render_game_over(canvas: &mut WindowCanvas, fonts: &mut FontManager) -> Result<(), String> {
+    let (width, height) = canvas.output_size()?;
+    let previous_color = canvas.draw_color();
+    canvas.set_blend_mode(BlendMode::Blend);
+    canvas.set_draw_color(Color::RGBA(0, 0, 0, 180));
+    canvas.fill_rect(Rect::new(0, 0, width, height))?;
+    canvas.set_blend_mode(BlendMode::None);
+    canvas.set_draw_color(previous_color);
+
+    let lines = [(&DIALOGUE_TEXT, "Game over"), (&NOTIFICATION_TEXT, "Press Z to try again")];
+    let mut y = height as i32 / 2 - DIALOGUE_TEXT.size as i32;
+    for (style, line) in lines.iter() {
+        let (line_width, line_height) = fonts.size_of(style, line).unwrap_or((0, 0));
+        draw_text(canvas, fonts, style, line, (width as i32 - line_width as i32) / 2, y)?;
+        y += line_height as i32;
+    }
+    Ok(())
+}
+
+// Foes across the top, the party below them, and the commands at the bottom.
+fn render_battle(
+    canvas: &mut WindowCanvas,
+    textures: &[Texture],
+    fonts: &mut FontManager,
+    battle: &Battle,
+    inventory: Option<&Inventory>,
+    item_database: &ItemDatabase,
+) -> Result<(), String> {
+    let (width, height) = canvas.output_size()?;
+    let previous_color = canvas.draw_color();
+    canvas.set_draw_color(Color::RGB(20, 20, 40));
+    canvas.fill_rect(Rect::new(0, 0, width, height))?;
+    canvas.set_draw_color(previous_color);
+
+    let mut y = NOTIFICATION_MARGIN;
+    for line in &battle.log {
+        if let Some((_, line_height)) = draw_text(canvas, fonts, &NOTIFICATION_TEXT, line, NOTIFICATION_MARGIN, y)? {
+            y += line_height as i32;
+        }
+    }
+
+    let current = battle.current();
+    let targets = battle.targets();
+    let target = match battle.phase {
+        Phase::Target => targets.get(battle.selected.min(targets.len().saturating_sub(1))).copied(),
+        _ => None,
+    };
+    for &(side, row) in &[(Side::Foes, height as i32 / 3), (Side::Party, height as i32 * 3 / 5)] {
+        let members: Vec<usize> = (0..battle.fighters.len()).filter(|&i| battle.fighters[i].side == side).collect();
+        for (slot, &i) in members.iter().enumerate() {
+            let fighter = &battle.fighters[i];
+            // Beaten foes are gone, fallen party members stay where they are.
+            if side == Side::Foes && fighter.health.dead() {
+                continue;
+            }
+            let x = width as i32 * (slot as i32 + 1) / (members.len() as i32 + 1);
+            let region = fighter.sprite.region;
+            let rect = Rect::from_center(Point::new(x, row), region.width() * BATTLE_SPRITE_SCALE, region.height() * BATTLE_SPRITE_SCALE);
+            canvas.copy(&textures[fighter.sprite.spritesheet.index()], region, rect)?;
+
+            let style = if Some(i) == target || Some(i) == current { &MENU_HIGHLIGHT_TEXT } else { &MENU_TEXT };
+            let (name_width, _) = fonts.size_of(style, &fighter.name).unwrap_or((0, 0));
+            draw_text(canvas, fonts, style, &fighter.name, x - name_width as i32 / 2, rect.top() - style.size as i32 - NOTIFICATION_MARGIN)?;
+            let (bar_width, bar_height) = BATTLE_BAR_SIZE;
+            let bar = Rect::new(x - bar_width as i32 / 2, rect.bottom() + NOTIFICATION_MARGIN, bar_width, bar_height);
+            render_health_bar(canvas, bar, &fighter.health)?;
+        }
+    }
+
+    let panel = Rect::new(
+        NOTIFICATION_MARGIN,
+        height as i32 - BATTLE_PANEL_HEIGHT as i32 - NOTIFICATION_MARGIN,
+        width - 2 * NOTIFICATION_MARGIN as u32,
+        BATTLE_PANEL_HEIGHT,
+    );
+    canvas.set_blend_mode(BlendMode::Blend);
+    canvas.set_draw_color(Color::RGBA(10, 10, 20, 220));
+    canvas.fill_rect(panel)?;
+    canvas.set_blend_mode(BlendMode::None);
+    canvas.set_draw_color(previous_color);
+
+    let mut y = panel.y() + MENU_PADDING;
+    for (i, fighter) in battle.fighters.iter().enumerate().filter(|(_, fighter)| fighter.side == Side::Party) {
+        let style = if Some(i) == current { &MENU_HIGHLIGHT_TEXT } else { &MENU_TEXT };
+        let line = format!("{}  HP {}/{}  MP {}/{}", fighter.name, fighter.health.current, fighter.health.max, fighter.stats.mp, fighter.stats.max_mp);
+        draw_text(canvas, fonts, style, &line, panel.x() + MENU_PADDING, y)?;
+        y += MENU_ROW_HEIGHT;
+    }
+
+    let x = panel.x() + panel.width() as i32 / 2;
+    let mut y = panel.y() + MENU_PADDING;
+    let party_turn = current.is_some_and(|i| battle.fighters[i].side == Side::Party);
+    match battle.phase {
+        Phase::Over(outcome) => {
+            let title = match outcome {
+                Outcome::Victory => "Victory!",
+                Outcome::Defeat => "Defeated...",
+                Outcome::Fled => "Got away.",
+            };
+            draw_text(canvas, fonts, &MENU_HIGHLIGHT_TEXT, title, x, y)?;
+            draw_text(canvas, fonts, &MENU_TEXT, "Press Z to continue", x, y + MENU_ROW_HEIGHT)?;
+        },
+        Phase::Item => {
+            let items = inventory.map(|inv| battle_items(inv, item_database)).unwrap_or_default();
+            for (row, (id, count)) in items.iter().enumerate() {
+                let style = if row == battle.selected { &MENU_HIGHLIGHT_TEXT } else { &MENU_TEXT };
+                let name = item_database.get(id).map_or(id.as_str(), |item| item.name.as_str());
+                draw_text(canvas, fonts, style, &format!("{} x {}", name, count), x, y)?;
+                y += MENU_ROW_HEIGHT;
+            }
+        },
+        Phase::Command | Phase::Target if party_turn => {
+            for (row, command) in BattleCommand::ALL.iter().enumerate() {
+                let style = if row == battle.command { &MENU_HIGHLIGHT_TEXT } else { &MENU_TEXT };
+                draw_text(canvas, fonts, style, command.label(), x, y)?;
+                y += MENU_ROW_HEIGHT;
+            }
+        },
+        _ => {},
+    }
+    Ok(())
+}
+
+// The stat block of everyone in the party, the player first.
+fn render_status(
+    canvas: &mut WindowCanvas,
+    fonts: &mut FontManager,
+    party: &[(String, Option<&Health>, &Stats)],
+    levels: &LevelTable,
+) -> Result<(), String> {
+    let (x, y, width, height) = MENU_PANEL;
+    let panel = Rect::new(x, y, width, height);
+    let previous_color = canvas.draw_color();
+    canvas.set_blend_mode(BlendMode::Blend);
+    canvas.set_draw_color(Color::RGBA(10, 10, 20, 220));
+    canvas.fill_rect(panel)?;
+    canvas.set_blend_mode(BlendMode::None);
+    canvas.set_draw_color(previous_color);
+
+    let x = panel.x() + MENU_PADDING;
+    let mut y = panel.y() + MENU_PADDING;
+    draw_text(canvas, fonts, &MENU_HIGHLIGHT_TEXT, "Status", x, y)?;
+    y += MENU_ROW_HEIGHT;
+    for (name, health, stats) in party {
+        let next = match levels.next(stats.level) {
+            Some(experience) => format!("{} xp, next level at {}", stats.experience, experience),
+            None => format!("{} xp", stats.experience),
+        };
+        let hp = health.map_or("-".to_string(), |hp| format!("{}/{}", hp.current, hp.max));
+        let lines = [
+            (&MENU_HIGHLIGHT_TEXT, format!("{}  level {}", name, stats.level)),
+            (&NOTIFICATION_TEXT, format!("HP {}  MP {}/{}  {}", hp, stats.mp, stats.max_mp, next)),
+            (&NOTIFICATION_TEXT, format!("Attack {}  Defence {}  Speed {}", stats.attack, stats.defence, stats.speed)),
+        ];
+        for (style, line) in lines.iter() {
+            if let Some((_, line_height)) = draw_text(canvas, fonts, style, line, x, y)? {
+                y += line_height as i32;
+            }
+        }
+        y += MENU_PADDING;
+    }
+    Ok(())
+}
+
+// Greedily breaks text into lines no wider than max_width. A word too long for a line gets one to itself.
+fn wrap_text(fonts: &mut FontManager, style: &TextStyle, text: &str, max_width: u32) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    for word in text.split_whitespace() {
+        let candidate = if line.is_empty() { word.to_string() } else { format!("{} {}", line, word) };
+        let (width, _) = fonts.size_of(style, &candidate).unwrap_or((0, 0));
+        if width > max_width && !line.is_empty() {
+            lines.push(line);
+            line = word.to_string();
+        } else {
+            line = candidate;
+        }
+    }
+    if !line.is_empty() {
+        lines.push(line);
+    }
+    lines
+}
+
+fn text_width(size: Size3, boxes: &DialogueBoxes, indent: u32) -> u32 {
+    boxes.get(size).region.width().saturating_sub(2 * DIALOGUE_MARGIN_X as u32 + indent)
+}
+
+fn lines_per_box(line_spacing: i32, size: Size3, boxes: &DialogueBoxes) -> usize {
+    let box_height = boxes.get(size).region.height() as i32;
+    (((box_height - 2 * DIALOGUE_MARGIN_Y) / line_spacing.max(1)).max(1)) as usize
+}
+
+// Picks the box for a line and splits its text into pages. Choices are shown on the last page,
+// so room is kept for them there, as much as the box has. The text starts `indent` pixels in to make
+// room for a portrait.
+fn layout_dialogue(
+    fonts: &mut FontManager,
+    item: &Dialogue_Single_item,
+    choices: usize,
+    boxes: &DialogueBoxes,
+    indent: u32,
+) -> (Size3, Vec<Vec<String>>) {
+    let line_spacing = fonts.line_spacing(&DIALOGUE_TEXT);
+    let size = match item.background_size {
+        Some(size) => size,
+        None => {
+            let needed = wrap_text(fonts, &DIALOGUE_TEXT, &item.dialogue_text, text_width(Size3::Large, boxes, indent)).len() + choices;
+            [Size3::Small, Size3::Medium, Size3::Large].iter()
+                .copied()
+                .find(|&size| needed <= lines_per_box(line_spacing, size, boxes))
+                .unwrap_or(Size3::Large)
+        }
+    };
+
+    let per_page = lines_per_box(line_spacing, size, boxes);
+    let mut lines = wrap_text(fonts, &DIALOGUE_TEXT, &item.dialogue_text, text_width(size, boxes, indent));
+
+    let mut pages = Vec::new();
+    while lines.len() + choices > per_page && lines.len() > per_page.saturating_sub(choices).max(1) {
+        let rest = lines.split_off(per_page.min(lines.len() - 1));
+        pages.push(lines);
+        lines = rest;
+    }
+    pages.push(lines);
+    (size, pages)
+}
+
+fn render_dialogue(
+    canvas: &mut WindowCanvas,
+    textures: &[Texture],
+    fonts: &mut FontManager,
+    active_dialogue: &ActiveDialogue,
+    previous_dialogue: &mut Dialogue_Helper,
+    dialogue_boxes: &DialogueBoxes,
+    speakers: &Speakers,
+) -> Result<(), String> {
+    let dialogue_item = match active_dialogue.current_line() {
+        Some(item) => item,
+        None => return Ok(()),
+    };
+    let choices = if active_dialogue.awaiting_choice() { active_dialogue.choices.len() } else { 0 };
+
+    let portrait = speakers.portrait(&dialogue_item.speaker_name);
+    let indent = match portrait {
+        Some(portrait) => portrait.region.width() * PORTRAIT_SCALE + PORTRAIT_GAP as u32,
+        None => 0,
+    };
+
+    if dialogue_item.dialogue_text != previous_dialogue.text || choices != previous_dialogue.choices {
+        // Lay out the new line
+        let (size, pages) = layout_dialogue(fonts, dialogue_item, choices, dialogue_boxes, indent);
+        *previous_dialogue = Dialogue_Helper {
+            text: dialogue_item.dialogue_text.clone(),
+            choices,
+            size,
+            pages,
+            pauses: dialogue_item.pauses.clone(),
+            ..Dialogue_Helper::default()
+        };
+    }
+
+    let (_, height) = canvas.output_size()?;
+    let box_sprite = dialogue_boxes.get(previous_dialogue.size);
+    let box_rect = Rect::new(0, height as i32 - box_sprite.region.height() as i32, box_sprite.region.width(), box_sprite.region.height());
+    canvas.copy(&textures[box_sprite.spritesheet.index()], box_sprite.region, box_rect)?;
+
+    // Name plate, sitting on top of the box's upper edge
+    if let Some((width, height)) = fonts.size_of(&NAME_PLATE_TEXT, &dialogue_item.speaker_name) {
+        let plate = Rect::new(
+            box_rect.x() + DIALOGUE_MARGIN_X,
+            box_rect.y() - height as i32 - NAME_PLATE_PADDING,
+            width + 2 * NAME_PLATE_PADDING as u32,
+            height + 2 * NAME_PLATE_PADDING as u32,
+        );
+        let previous_color = canvas.draw_color();
+        canvas.set_draw_color(Color::RGB(20, 20, 20));
+        canvas.fill_rect(plate)?;
+        canvas.set_draw_color(previous_color);
+        draw_text(canvas, fonts, &NAME_PLATE_TEXT, &dialogue_item.speaker_name, plate.x() + NAME_PLATE_PADDING, plate.y() + NAME_PLATE_PADDING)?;
+    }
+
+    if let Some(portrait) = portrait {
+        let portrait_rect = Rect::new(
+            box_rect.x() + DIALOGUE_MARGIN_X,
+            box_rect.y() + DIALOGUE_MARGIN_Y,
+            portrait.region.width() * PORTRAIT_SCALE,
+            portrait.region.height() * PORTRAIT_SCALE,
+        );
+        canvas.copy(&textures[portrait.spritesheet.index()], portrait.region, portrait_rect)?;
+    }
+
+    let text_x = box_rect.x() + DIALOGUE_MARGIN_X + indent as i32;
+    let line_spacing = fonts.line_spacing(&DIALOGUE_TEXT);
+    let mut y = box_rect.y() + DIALOGUE_MARGIN_Y;
+    let page = previous_dialogue.pages.get(previous_dialogue.page).cloned().unwrap_or_default();
+    for (line, visible) in page.iter().zip(previous_dialogue.visible_lines()) {
+        draw_text_prefix(canvas, fonts, &DIALOGUE_TEXT, line, visible.chars().count(), text_x, y)?;
+        y += line_spacing;
+    }
+
+    if choices > 0 && previous_dialogue.on_last_page() && previous_dialogue.page_revealed() {
+        // More choices than fit below the text scroll, keeping the selected one in view.
+        let room = lines_per_box(line_spacing, previous_dialogue.size, dialogue_boxes).saturating_sub(page.len()).max(1);
+        let first = (active_dialogue.selected + 1).saturating_sub(room);
+        for (i, choice) in active_dialogue.choices.iter().enumerate().skip(first).take(room) {
+            let marker = if i == active_dialogue.selected { ">" } else { " " };
+            draw_text(canvas, fonts, &DIALOGUE_TEXT, &format!("{} {}", marker, choice.text), text_x + DIALOGUE_CHOICE_INDENT, y)?;
+            y += line_spacing;
+        }
+    }
+
+    Ok(())
+}
+
+fn render_menu(
+    canvas: &mut WindowCanvas,
+    textures: &[Texture],
+    fonts: &mut FontManager,
+    menu: &MenuState,
+    inventory: &Inventory,
+    equipment: Option<&Equipment>,
+    item_database: &ItemDatabase,
+) -> Result<(), String> {
+    let (x, y, width, height) = MENU_PANEL;
+    let panel = Rect::new(x, y, width, height);
+    let previous_color = canvas.draw_color();
+    canvas.set_blend_mode(BlendMode::Blend);
+    canvas.set_draw_color(Color::RGBA(10, 10, 20, 220));
+    canvas.fill_rect(panel)?;
+    canvas.set_blend_mode(BlendMode::None);
+    canvas.set_draw_color(previous_color);
+
+    let title = format!("Inventory ({}/{})", inventory.slots.len(), inventory.capacity);
+    draw_text(canvas, fonts, &MENU_HIGHLIGHT_TEXT, &title, panel.x() + MENU_PADDING, panel.y() + MENU_PADDING)?;
+
+    // Room is left at the bottom for the description and the actions.
+    let list_top = panel.y() + MENU_PADDING + MENU_ROW_HEIGHT;
+    let list_bottom = panel.bottom() - MENU_PADDING - 3 * MENU_ROW_HEIGHT;
+    let visible_rows = ((list_bottom - list_top) / MENU_ROW_HEIGHT).max(1) as usize;
+    let first_row = (menu.selected + 1).saturating_sub(visible_rows);
+
+    let rows = entries(inventory, equipment);
+    if rows.is_empty() {
+        draw_text(canvas, fonts, &MENU_TEXT, "Nothing here yet.", panel.x() + MENU_PADDING, list_top)?;
+    }
+    for (i, entry) in rows.iter().enumerate().skip(first_row).take(visible_rows) {
+        let row_y = list_top + (i - first_row) as i32 * MENU_ROW_HEIGHT;
+        let selected = i == menu.selected;
+        let style = if selected { &MENU_HIGHLIGHT_TEXT } else { &MENU_TEXT };
+        let item = item_database.get(entry.item());
+
+        if selected {
+            draw_text(canvas, fonts, style, ">", panel.x() + MENU_PADDING, row_y)?;
+        }
+        if let Some(item) = item {
+            let icon = item.icon;
+            let icon_rect = Rect::new(
+                panel.x() + 2 * MENU_PADDING,
+                row_y,
+                icon.region.width() * MENU_ICON_SCALE,
+                icon.region.height() * MENU_ICON_SCALE,
+            );
+            canvas.copy(&textures[icon.spritesheet.index()], icon.region, icon_rect)?;
+        }
+
+        let name = item.map_or(entry.item(), |item| item.name.as_str());
+        let label = match entry {
+            MenuEntry::Stack { count, .. } if *count > 1 => format!("{} x {}", name, count),
+            MenuEntry::Stack { .. } => name.to_string(),
+            MenuEntry::Equipped { slot, .. } => format!("{} (equipped, {})", name, slot),
+        };
+        draw_text(canvas, fonts, style, &label, panel.x() + 2 * MENU_PADDING + 40, row_y)?;
+    }
+
+    let selected_item = rows.get(menu.selected).and_then(|entry| item_database.get(entry.item()));
+    if let Some(item) = selected_item {
+        draw_text(canvas, fonts, &MENU_TEXT, &item.description, panel.x() + MENU_PADDING, list_bottom + MENU_ROW_HEIGHT / 2)?;
+    }
+
+    let mut action_x = panel.x() + MENU_PADDING;
+    let actions_y = panel.bottom() - MENU_PADDING - MENU_ROW_HEIGHT;
+    for action in MenuAction::ALL.iter() {
+        let style = if *action == menu.action { &MENU_HIGHLIGHT_TEXT } else { &MENU_TEXT };
+        let label = if *action == menu.action { format!("[{}]", action.label()) } else { action.label().to_string() };
+        if let Some((width, _)) = draw_text(canvas, fonts, style, &label, action_x, actions_y)? {
+            action_x += width as i32 + MENU_PADDING;
+        }
+    }
+
+    Ok(())
+}
+
+fn render_collision_editor(
+    canvas: &mut WindowCanvas,
+    fonts: &mut FontManager,
+    editor: &CollisionEditor,
+    position: &ReadStorage<Position>,
+    collision: &ReadStorage<CollisionBox>,
+    origin: Point,
+) -> Result<(), String> {
+    let previous_color = canvas.draw_color();
+    canvas.set_blend_mode(BlendMode::Blend);
 
-    // render a surface, and convert it to a texture bound to the canvas
-    //println!("{}", text);
-    let surface = font.render(text)
-        .blended(Color::RGBA(255, 0, 0, 255))
-        .map_err(|e| e.to_string())?;
-    let texture = texture_creator.create_texture_from_surface(&surface)
-        .map_err(|e| e.to_string())?;
-    Ok(texture)
+    let selected = editor.selected.and_then(|entity| Some(collider_rect(position.get(entity)?, collision.get(entity)?)));
+    if let Some(mut rect) = selected {
+        rect.offset(origin.x(), origin.y());
+        canvas.set_draw_color(Color::RGBA(255, 200, 0, 80));
+        canvas.fill_rect(rect)?;
+        canvas.set_draw_color(Color::RGBA(255, 200, 0, 255));
+        canvas.fill_rect(handle_rect(rect))?;
+    }
+    if let Some(mut rect) = editor.drawing() {
+        rect.offset(origin.x(), origin.y());
+        canvas.set_draw_color(Color::RGBA(255, 255, 255, 255));
+        canvas.draw_rect(rect)?;
+    }
+
+    canvas.set_blend_mode(BlendMode::None);
+    canvas.set_draw_color(previous_color);
+
+    let (_, height) = canvas.output_size()?;
+    let help = "Collision editor: drag to draw, move or resize. Right click or Delete removes. S saves, L reloads, F3 exits.";
+    draw_text(canvas, fonts, &NOTIFICATION_TEXT, help, NOTIFICATION_MARGIN, height as i32 - 2 * NOTIFICATION_MARGIN - NOTIFICATION_TEXT.size as i32)?;
+    Ok(())
+}
+
+fn render_level_editor(
+    canvas: &mut WindowCanvas,
+    fonts: &mut FontManager,
+    editor: &LevelEditor,
+    (position, collision, sprite, placed): (&ReadStorage<Position>, &ReadStorage<CollisionBox>, &ReadStorage<Sprite>, &ReadStorage<Placed>),
+    origin: Point,
+) -> Result<(), String> {
+    let previous_color = canvas.draw_color();
+    canvas.set_blend_mode(BlendMode::Blend);
+
+    let mut selected = None;
+    for (entity, pos, col, sprite, Placed(placement)) in (position.fetched_entities(), position, collision.maybe(), sprite.maybe(), placed).join() {
+        let mut rect = placed_rect(pos, col, sprite);
+        rect.offset(origin.x(), origin.y());
+        if editor.selected == Some(entity) {
+            canvas.set_draw_color(Color::RGBA(255, 200, 0, 80));
+            canvas.fill_rect(rect)?;
+            selected = Some((pos.0, placement));
+        }
+        canvas.set_draw_color(Color::RGBA(0, 200, 255, 255));
+        canvas.draw_rect(rect)?;
+    }
+
+    let (width, height) = canvas.output_size()?;
+    let panel = Rect::new(width as i32 - EDITOR_PANEL_WIDTH as i32, 0, EDITOR_PANEL_WIDTH, height);
+    canvas.set_draw_color(Color::RGBA(10, 10, 20, 200));
+    canvas.fill_rect(panel)?;
+    canvas.set_blend_mode(BlendMode::None);
+    canvas.set_draw_color(previous_color);
+
+    let x = panel.x() + NOTIFICATION_MARGIN;
+    let mut y = NOTIFICATION_MARGIN;
+    for (i, (kind, _)) in KINDS.iter().enumerate() {
+        let style = if i == editor.palette_index { &MENU_HIGHLIGHT_TEXT } else { &NOTIFICATION_TEXT };
+        // Number keys 1 to 9 and then 0 pick the first ten.
+        let label = match i {
+            0..=8 => format!("{} {}", i + 1, kind),
+            9 => format!("0 {}", kind),
+            _ => format!("  {}", kind),
+        };
+        draw_text(canvas, fonts, style, &label, x, y)?;
+        y += EDITOR_ROW_HEIGHT;
+    }
+
+    if let Some((position, placement)) = selected {
+        y += EDITOR_ROW_HEIGHT;
+        let title = format!("{} at ({}, {})", placement.kind, position.x(), position.y());
+        draw_text(canvas, fonts, &MENU_HIGHLIGHT_TEXT, &title, x, y)?;
+        for (i, name) in placement.property_names().iter().enumerate() {
+            y += EDITOR_ROW_HEIGHT;
+            let chosen = i == editor.property_index;
+            let value = match &editor.editing {
+                Some(value) if chosen => format!("{}_", value),
+                _ => placement.property(name).unwrap_or("").to_string(),
+            };
+            let style = if chosen { &MENU_HIGHLIGHT_TEXT } else { &NOTIFICATION_TEXT };
+            draw_text(canvas, fonts, style, &format!("{}: {}", name, value), x, y)?;
+        }
+    }
+
+    let help = "Level editor: click places or selects, drag moves, Delete removes. Enter edits. Ctrl+Z/Y undo/redo, Ctrl+S saves, F4 exits.";
+    draw_text(canvas, fonts, &NOTIFICATION_TEXT, help, NOTIFICATION_MARGIN, height as i32 - 2 * NOTIFICATION_MARGIN - NOTIFICATION_TEXT.size as i32)?;
+    Ok(())
+}
+
+// Draws the layers of the given kinds in the order they are in the map, skipping tiles that are off screen.
+fn render_tiles(
+    canvas: &mut WindowCanvas,
+    textures: &[Texture],
+    tilemap: &TileMap,
+    origin: Point,
+    kinds: &[LayerKind],
+) -> Result<(), String> {
+    let (width, height) = canvas.output_size()?;
+    let screen = Rect::new(0, 0, width, height);
+
+    for layer in tilemap.layers.iter().filter(|layer| kinds.contains(&layer.kind)) {
+        for (i, &id) in layer.tiles.iter().enumerate() {
+            let (column, row) = (i as u32 % tilemap.width, i as u32 / tilemap.width);
+            let mut screen_rect = tilemap.tile_rect(column, row);
+            screen_rect.offset(origin.x(), origin.y());
+            if !screen.has_intersection(screen_rect) {
+                continue;
+            }
+            if let Some(tile) = tilemap.tile_sprite(id) {
+                canvas.copy(&textures[tile.spritesheet.index()], tile.region, screen_rect)?;
+            }
+        }
+    }
+    Ok(())
 }
 
 pub fn render(
     canvas: &mut WindowCanvas,
     background: Color,
     textures: &[Texture],
+    fonts: &mut FontManager,
     (
         position, 
         sprite,
         collision,
         interaction,
         gamestate,
-        dialogue_list,
+        active_dialogue,
         mut previous_dialogue,
+        dialogue_boxes,
+        speakers,
+        notifications,
+        keyboard_controlled,
+        inventory,
+        equipment,
+        menu,
+        item_database,
+        tilemap,
+        static_collider,
+        placed,
+        hitbox,
+        health,
+        battle,
+        stats,
+        party_member,
+        name,
+        levels,
     ): SystemData,
-    draw_bounding_boxes: bool,
-    draw_interaction_zone: bool,
     background_texture: &Texture,
+    overlays: Overlays,
 ) -> Result<(), String> {
     canvas.set_draw_color(background);
     canvas.clear();
@@ -76,9 +734,15 @@ pub fn render(
 
     let (width, height) = canvas.output_size()?;
     let origin = Point::new(width as i32 / 2, height as i32 /2);
+    render_tiles(canvas, textures, &tilemap, origin, &[LayerKind::Ground, LayerKind::Decoration])?;
     canvas.set_draw_color(Color::RGB(255, 0, 0));
     
-    for (pos, sprite, col) in (&position, &sprite, (&collision).maybe()).join() {
+    for (pos, sprite, col, hp) in (&position, &sprite, (&collision).maybe(), (&health).maybe()).join() {
+        if let Some(hp) = hp.filter(|hp| hp.invulnerable()) {
+            if hp.invulnerable_ms as u32 / BLINK_MS % 2 == 1 {
+                continue;
+            }
+        }
         match col {
             Some(col) => {
                 update_canvas(
@@ -88,7 +752,7 @@ pub fn render(
                     canvas,
                     textures,
                 )?;
-                if draw_bounding_boxes {
+                if overlays.bounding_boxes {
                     let screen_coord = origin + pos.0;
                     canvas.draw_rect(Rect::from_center(screen_coord, (*col).width, (*col).height))?;
                 }
@@ -105,27 +769,83 @@ pub fn render(
         }
     }
     
-    let texture_creator = canvas.texture_creator();
-    let mut txt_texture = text_to_texture(&texture_creator, &previous_dialogue.text).unwrap(); 
+    render_tiles(canvas, textures, &tilemap, origin, &[LayerKind::Overhead])?;
+
+    for (pos, hp, col, _) in (&position, &health, &collision, !&keyboard_controlled).join() {
+        if hp.current < hp.max {
+            let mut bar = Rect::new(0, 0, col.width, SMALL_HEALTH_BAR_HEIGHT);
+            bar.center_on(origin + pos.0 - Point::new(0, (col.height / 2 + SMALL_HEALTH_BAR_HEIGHT) as i32));
+            render_health_bar(canvas, bar, hp)?;
+        }
+    }
+
+    let editing_collision = *gamestate == Gamestate::CollisionEditor;
+    if overlays.bounding_boxes || editing_collision {
+        canvas.set_draw_color(Color::RGB(255, 200, 0));
+        for (pos, col, _) in (&position, &collision, &static_collider).join() {
+            let mut rect = collider_rect(pos, col);
+            rect.offset(origin.x(), origin.y());
+            canvas.draw_rect(rect)?;
+        }
+        canvas.set_draw_color(Color::RGB(255, 0, 0));
+    }
+    if editing_collision {
+        render_collision_editor(canvas, fonts, overlays.collision_editor, &position, &collision, origin)?;
+    }
+    if *gamestate == Gamestate::LevelEditor {
+        render_level_editor(canvas, fonts, overlays.level_editor, (&position, &collision, &sprite, &placed), origin)?;
+    }
+
+    let mut y = NOTIFICATION_MARGIN;
+    for message in notifications.current() {
+        if let Some((_, height)) = draw_text(canvas, fonts, &NOTIFICATION_TEXT, message, NOTIFICATION_MARGIN, y)? {
+            y += height as i32;
+        }
+    }
+
+    for (hp, _) in (&health, &keyboard_controlled).join() {
+        let (bar_width, bar_height) = HEALTH_BAR_SIZE;
+        let bar = Rect::new(width as i32 - bar_width as i32 - NOTIFICATION_MARGIN, NOTIFICATION_MARGIN, bar_width, bar_height);
+        render_health_bar(canvas, bar, hp)?;
+        let label = format!("{}/{}", hp.current, hp.max);
+        draw_text(canvas, fonts, &NOTIFICATION_TEXT, &label, bar.x(), bar.bottom())?;
+    }
+
+    if *gamestate == Gamestate::Menu {
+        for (_, inv, equip) in (&keyboard_controlled, &inventory, (&equipment).maybe()).join() {
+            render_menu(canvas, textures, fonts, &menu, inv, equip, &item_database)?;
+        }
+    }
 
     if *gamestate == Gamestate::Dialogue {
-        if let Some(dialogue_item) = dialogue_list.front() {
-
-            if dialogue_item.dialogue_text != previous_dialogue.text {
-                // Update the texture if there's new dialogue
-                txt_texture = text_to_texture(&texture_creator, &(dialogue_item.dialogue_text)).unwrap();
-                let TextureQuery { width, height, .. } = txt_texture.query();
-                previous_dialogue.text = dialogue_item.dialogue_text.clone();
-                previous_dialogue.width = width;
-                previous_dialogue.height = height;
+        render_dialogue(canvas, textures, fonts, &active_dialogue, &mut previous_dialogue, &dialogue_boxes, &speakers)?;
+    }
+
+    if *gamestate == Gamestate::Battle {
+        let inv = (&keyboard_controlled, &inventory).join().map(|(_, inv)| inv).next();
+        render_battle(canvas, textures, fonts, &battle, inv, &item_database)?;
+    }
+
+    if *gamestate == Gamestate::Status {
+        let mut party: Vec<(String, Option<&Health>, &Stats)> = Vec::new();
+        for (st, hp, named, player, _) in (&stats, (&health).maybe(), (&name).maybe(), (&keyboard_controlled).maybe(), keyboard_controlled.mask() | party_member.mask()).join() {
+            let fallback = if player.is_some() { "You" } else { "Ally" };
+            let entry = (named.map_or(fallback.to_string(), |name| name.0.clone()), hp, st);
+            if player.is_some() {
+                party.insert(0, entry);
+            } else {
+                party.push(entry);
             }
-            canvas.copy(&txt_texture, None, Some(Rect::new(0,600-200, previous_dialogue.width, previous_dialogue.height)))?;
         }
-        
+        render_status(canvas, fonts, &party, &levels)?;
+    }
+
+    if *gamestate == Gamestate::GameOver {
+        render_game_over(canvas, fonts)?;
     }
 
     // Debug function
-    if draw_interaction_zone {
+    if overlays.interaction_zones {
         for intzone in (&interaction).join() {
             
             let mut zone = intzone.rect;
@@ -134,10 +854,16 @@ pub fn render(
             
             canvas.draw_rect(zone)?;
         }
+        for hb in (&hitbox).join() {
+            let mut rect = hb.rect;
+            rect.offset(origin.x(), origin.y());
+            canvas.draw_rect(rect)?;
+        }
     }
 
     //canvas.copy(&texture, None, Some(Rect::new(0,0,200,200)))?;
     canvas.present();
+    fonts.end_frame();
 
     Ok(())
 }
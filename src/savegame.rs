@@ -0,0 +1,289 @@
+use crate::components::*;
+use crate::items::ItemDatabase;
+use crate::level::{self, Placed, Placement};
+use crate::variables::WorldVariables;
+use std::{collections::{BTreeMap, HashSet}, fs, path::Path};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use specs::prelude::*;
+use sdl2::rect::Point;
+
+// Save games are JSON files in SAVE_DIRECTORY, one per slot. What was placed in the level is spawned
+// again from its placement and then given back its state, like how often it was used or whether a
+// door is open. Things that are there from the start of every run, the player and the named
+// characters from add_player and add_reaper, are updated where they are instead.
+//
+// Bump SAVE_VERSION whenever the format changes, older saves are refused rather than half loaded.
+
+pub const SAVE_VERSION: u64 = 4;
+pub const SAVE_DIRECTORY: &str = "saves";
+pub const SAVE_SLOTS: usize = 3;
+
+#[derive(Serialize, Deserialize)]
+struct SaveGame {
+    version: u64,
+    gamestate: Gamestate,
+    variables: WorldVariables,
+    player: Option<SavedPlayer>,
+    characters: Vec<SavedCharacter>,
+    placed: Vec<SavedEntity>,
+    // Items lying around that weren't placed, like the ones item spawners drop.
+    dropped: Vec<SavedItem>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedPlayer {
+    x: i32,
+    y: i32,
+    inventory: Option<Inventory>,
+    equipment: Option<Equipment>,
+    health: Option<Health>,
+    stats: Option<Stats>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedCharacter {
+    name: String,
+    x: i32,
+    y: i32,
+    interactions: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedEntity {
+    kind: String,
+    x: i32,
+    y: i32,
+    properties: BTreeMap<String, String>,
+    interactions: Option<i64>,
+    health: Option<Health>,
+    // Party members level up too.
+    stats: Option<Stats>,
+    lever: Option<Lever>,
+    door_open: Option<bool>,
+    switch_on: Option<bool>,
+    pickup: Option<Pickup>,
+    collectible: Option<Collectible>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedItem {
+    item: String,
+    x: i32,
+    y: i32,
+}
+
+pub fn slot_path(slot: usize) -> String {
+    format!("{}/slot{}.json", SAVE_DIRECTORY, slot)
+}
+
+pub fn slot_exists(slot: usize) -> bool {
+    Path::new(&slot_path(slot)).exists()
+}
+
+pub fn save(world: &World, slot: usize) -> Result<(), String> {
+    let entities = world.entities();
+    let position = world.read_storage::<Position>();
+    let placed = world.read_storage::<Placed>();
+    let interactable = world.read_storage::<Interactable>();
+    let point = |entity: Entity| position.get(entity).map_or(Point::new(0, 0), |pos| pos.0);
+
+    let player = (&entities, &world.read_storage::<KeyboardControlled>()).join()
+        .map(|(entity, _)| SavedPlayer {
+            x: point(entity).x(),
+            y: point(entity).y(),
+            inventory: world.read_storage::<Inventory>().get(entity).cloned(),
+            equipment: world.read_storage::<Equipment>().get(entity).cloned(),
+            health: world.read_storage::<Health>().get(entity).cloned(),
+            stats: world.read_storage::<Stats>().get(entity).cloned(),
+        })
+        .next();
+
+    // Named things that were placed are respawned with the rest of the level.
+    let characters = (&entities, &world.read_storage::<Name>(), !&placed).join()
+        .map(|(entity, name, _)| SavedCharacter {
+            name: name.0.clone(),
+            x: point(entity).x(),
+            y: point(entity).y(),
+            interactions: interactable.get(entity).map(|i| i.interactions),
+        })
+        .collect();
+
+    let placed_entities = (&entities, &placed).join()
+        .map(|(entity, Placed(placement))| SavedEntity {
+            kind: placement.kind.clone(),
+            x: point(entity).x(),
+            y: point(entity).y(),
+            properties: placement.properties.clone(),
+            interactions: interactable.get(entity).map(|i| i.interactions),
+            health: world.read_storage::<Health>().get(entity).cloned(),
+            stats: world.read_storage::<Stats>().get(entity).cloned(),
+            lever: world.read_storage::<Lever>().get(entity).cloned(),
+            door_open: world.read_storage::<Door>().get(entity).map(|door| door.open),
+            switch_on: world.read_storage::<Switch>().get(entity).map(|switch| switch.on),
+            pickup: world.read_storage::<Pickup>().get(entity).cloned(),
+            collectible: world.read_storage::<Collectible>().get(entity).cloned(),
+        })
+        .collect();
+
+    let dropped = (&entities, &world.read_storage::<Collectible>(), !&placed).join()
+        .map(|(entity, collectible, _)| SavedItem {
+            item: collectible.item.clone(),
+            x: point(entity).x(),
+            y: point(entity).y(),
+        })
+        .collect();
+
+    let save = SaveGame {
+        version: SAVE_VERSION,
+        gamestate: *world.read_resource::<Gamestate>(),
+        variables: (*world.read_resource::<WorldVariables>()).clone(),
+        player,
+        characters,
+        placed: placed_entities,
+        dropped,
+    };
+
+    let path = slot_path(slot);
+    let contents = serde_json::to_string_pretty(&save).map_err(|e| format!("Could not save {}: {}", path, e))?;
+    fs::create_dir_all(SAVE_DIRECTORY).map_err(|e| format!("Could not create {}: {}", SAVE_DIRECTORY, e))?;
+    fs::write(&path, contents).map_err(|e| format!("Could not save {}: {}", path, e))
+}
+
+fn read(path: &str) -> Result<SaveGame, String> {
+    let contents = fs::read_to_string(path).map_err(|e| format!("Could not read {}: {}", path, e))?;
+    let value: Value = serde_json::from_str(&contents).map_err(|e| format!("{}: {}", path, e))?;
+    // Checked first so a save in another format says so, instead of failing on some missing field.
+    match value.get("version").and_then(Value::as_u64) {
+        Some(SAVE_VERSION) => {},
+        Some(version) => return Err(format!("{} is save version {}, this game reads version {}", path, version, SAVE_VERSION)),
+        None => return Err(format!("{} is not a save game", path)),
+    }
+    serde_json::from_value(value).map_err(|e| format!("{}: {}", path, e))
+}
+
+// Puts the world back the way it was saved. What was saved is spawned before anything in the world is
+// removed, so nothing is changed if the file can't be read or something in it can't be spawned.
+pub fn load(world: &mut World, slot: usize) -> Result<(), String> {
+    let save = read(&slot_path(slot))?;
+
+    // What was placed and what was dropped since, all replaced by what the save has.
+    let replaced: Vec<Entity> = (&world.entities(), (&world.read_storage::<Placed>()).maybe(), (&world.read_storage::<Collectible>()).maybe()).join()
+        .filter(|(_, placed, collectible)| placed.is_some() || collectible.is_some())
+        .map(|(entity, _, _)| entity)
+        .collect();
+    let before: HashSet<Entity> = world.entities().join().collect();
+    if let Err(e) = spawn_saved(world, &save) {
+        let spawned: Vec<Entity> = world.entities().join().filter(|entity| !before.contains(entity)).collect();
+        world.delete_entities(&spawned).ok();
+        return Err(e);
+    }
+    world.delete_entities(&replaced).ok();
+
+    if let Some(saved) = &save.player {
+        let player: Vec<Entity> = (&world.entities(), &world.read_storage::<KeyboardControlled>()).join()
+            .map(|(entity, _)| entity)
+            .collect();
+        for entity in player {
+            move_to(world, entity, Point::new(saved.x, saved.y));
+            if let Some(inventory) = &saved.inventory {
+                world.write_storage::<Inventory>().insert(entity, inventory.clone()).ok();
+            }
+            if let Some(equipment) = &saved.equipment {
+                world.write_storage::<Equipment>().insert(entity, equipment.clone()).ok();
+            }
+            if let Some(health) = &saved.health {
+                world.write_storage::<Health>().insert(entity, health.clone()).ok();
+            }
+            if let Some(stats) = &saved.stats {
+                world.write_storage::<Stats>().insert(entity, stats.clone()).ok();
+            }
+            world.write_storage::<Knockback>().remove(entity);
+        }
+    }
+    for saved in &save.characters {
+        let matching: Vec<Entity> = (&world.entities(), &world.read_storage::<Name>(), !&world.read_storage::<Placed>()).join()
+            .filter(|(_, name, _)| name.0 == saved.name)
+            .map(|(entity, _, _)| entity)
+            .collect();
+        for entity in matching {
+            move_to(world, entity, Point::new(saved.x, saved.y));
+            if let (Some(interactions), Some(interactable)) = (saved.interactions, world.write_storage::<Interactable>().get_mut(entity)) {
+                interactable.interactions = interactions;
+            }
+        }
+    }
+
+    *world.write_resource::<WorldVariables>() = save.variables;
+    *world.write_resource::<Gamestate>() = save.gamestate;
+    world.write_resource::<Signals>().queue.clear();
+    Ok(())
+}
+
+fn spawn_saved(world: &mut World, save: &SaveGame) -> Result<(), String> {
+    for saved in &save.placed {
+        let entity = level::spawn(world, &Placement {
+            kind: saved.kind.clone(),
+            position: Point::new(saved.x, saved.y),
+            properties: saved.properties.clone(),
+        })?;
+        restore_entity(world, entity, saved);
+    }
+    for item in &save.dropped {
+        crate::spawn_collectible(world, item.x, item.y, &item.item)?;
+    }
+    Ok(())
+}
+
+// Also stops it, so nothing carries on walking from where it was before loading.
+fn move_to(world: &mut World, entity: Entity, point: Point) {
+    if let Some(pos) = world.write_storage::<Position>().get_mut(entity) {
+        pos.0 = point;
+    }
+    if let Some(vel) = world.write_storage::<Velocity>().get_mut(entity) {
+        vel.direction.clear();
+    }
+    if let Some(flag) = world.write_storage::<FlagForMovement>().get_mut(entity) {
+        flag.moving = false;
+        flag.new_pos = Position(point);
+    }
+}
+
+fn restore_entity(world: &mut World, entity: Entity, saved: &SavedEntity) {
+    // Whatever was used gets its animation played out again by the Animator.
+    if let (Some(interactions), Some(interactable)) = (saved.interactions, world.write_storage::<Interactable>().get_mut(entity)) {
+        interactable.interactions = interactions;
+    }
+    if let Some(health) = &saved.health {
+        world.write_storage::<Health>().insert(entity, health.clone()).ok();
+    }
+    if let Some(stats) = &saved.stats {
+        world.write_storage::<Stats>().insert(entity, stats.clone()).ok();
+    }
+    // The Switches system brings the lever's look in line.
+    if let Some(lever) = &saved.lever {
+        world.write_storage::<Lever>().insert(entity, lever.clone()).ok();
+    }
+    if let (Some(on), Some(switch)) = (saved.switch_on, world.write_storage::<Switch>().get_mut(entity)) {
+        switch.on = on;
+        world.write_storage::<Sprite>().insert(entity, switch.sprite()).ok();
+    }
+    if let (Some(open), Some(door)) = (saved.door_open, world.write_storage::<Door>().get_mut(entity)) {
+        door.open = open;
+        world.write_storage::<Sprite>().insert(entity, door.sprite()).ok();
+        if open {
+            world.write_storage::<CollisionBox>().remove(entity);
+        }
+    }
+    if let Some(pickup) = &saved.pickup {
+        world.write_storage::<Pickup>().insert(entity, pickup.clone()).ok();
+    }
+    // Fruit is picked at random when spawned, so it has to be the same one again.
+    if let Some(collectible) = &saved.collectible {
+        let icon = world.read_resource::<ItemDatabase>().get(&collectible.item).map(|item| item.icon);
+        if let Some(icon) = icon {
+            world.write_storage::<Sprite>().insert(entity, icon).ok();
+        }
+        world.write_storage::<Collectible>().insert(entity, collectible.clone()).ok();
+    }
+}
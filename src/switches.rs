@@ -0,0 +1,104 @@
+use specs::{System, Entities, ReadStorage, WriteStorage, ReadExpect, WriteExpect, Read, LazyUpdate, Builder, join::Join};
+use crate::components::*;
+use crate::items::ItemDatabase;
+use crate::variables::{Variable, WorldVariables};
+
+pub struct Switches;
+
+impl<'a> System<'a> for Switches {
+
+    type SystemData = (
+        Entities<'a>,
+        WriteExpect<'a, Signals>,
+        ReadStorage<'a, Name>,
+        WriteStorage<'a, Switch>,
+        WriteStorage<'a, Sprite>,
+        WriteStorage<'a, Lever>,
+        WriteStorage<'a, Door>,
+        WriteStorage<'a, CollisionBox>,
+        ReadStorage<'a, ItemSpawner>,
+        ReadStorage<'a, Position>,
+        ReadExpect<'a, ItemDatabase>,
+        WriteExpect<'a, WorldVariables>,
+        Read<'a, LazyUpdate>,
+    );
+
+    fn run(&mut self, (
+        entities,
+        mut signals,
+        name,
+        mut switch,
+        mut sprite,
+        mut lever,
+        mut door,
+        mut collisionbox,
+        spawner,
+        position,
+        item_database,
+        mut variables,
+        lazy,
+    ): Self::SystemData) {
+        while let Some((target, signal)) = signals.queue.pop_front() {
+            let targets: Vec<_> = (&entities, &name).join()
+                .filter(|(_, name)| name.0 == target)
+                .map(|(entity, _)| entity)
+                .collect();
+            if targets.is_empty() {
+                println!("Nothing is named \"{}\", the signal goes nowhere.", target);
+            }
+
+            for entity in targets {
+                if let Some(switch) = switch.get_mut(entity) {
+                    switch.receive(signal);
+                    if let Some(sprite) = sprite.get_mut(entity) {
+                        *sprite = switch.sprite();
+                    }
+                }
+                // Levers that are targets move along, but don't pass the signal on again.
+                if let Some(lever) = lever.get_mut(entity) {
+                    lever.receive(signal);
+                    if let Some(name) = &lever.variable {
+                        variables.set(name, Variable::Bool(lever.on));
+                    }
+                }
+                if let Some(door) = door.get_mut(entity) {
+                    door.receive(signal);
+                    if door.open {
+                        collisionbox.remove(entity);
+                    } else {
+                        collisionbox.insert(entity, door.collision.clone()).ok();
+                    }
+                    if let Some(sprite) = sprite.get_mut(entity) {
+                        *sprite = door.sprite();
+                    }
+                }
+                if let (Some(spawner), Some(pos)) = (spawner.get(entity), position.get(entity)) {
+                    match (signal, item_database.get(&spawner.item)) {
+                        (Signal::Deactivate, _) => {},
+                        (_, Some(item)) => {
+                            lazy.create_entity(&entities)
+                                .with(*pos)
+                                .with(CollisionBox {
+                                    width: item.icon.region.width(),
+                                    height: item.icon.region.height(),
+                                })
+                                .with(Unplayable)
+                                .with(Collectible { item: item.id.clone() })
+                                .with(item.icon)
+                                .build();
+                        },
+                        (_, None) => println!("Spawner has an unknown item \"{}\"", spawner.item),
+                    }
+                }
+            }
+        }
+
+        // A lever's look follows its state, whether it was used or signalled.
+        for (lever, switch, sprite) in (&lever, &mut switch, &mut sprite).join() {
+            if switch.on != lever.on {
+                switch.on = lever.on;
+                *sprite = switch.sprite();
+            }
+        }
+    }
+}
@@ -0,0 +1,76 @@
+use std::{collections::HashMap, fs, path::Path};
+use regex::Regex;
+
+// Texture manifest format, one texture per line:
+//
+// player assets/bardo.png          Name, then the image file.
+//
+// Lines starting with // are comments. Everything else refers to textures by name, the registry hands
+// out the handle that goes in Sprite::spritesheet, so the order of the manifest doesn't matter.
+
+// Which texture a sprite is cut from. Handles stay the same for as long as the game runs, and only the
+// registry hands them out, so every one refers to a texture in the manifest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TextureHandle(usize);
+
+impl TextureHandle {
+    // Where the texture is among the ones loaded in the order of TextureRegistry::paths.
+    pub fn index(self) -> usize {
+        self.0
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct TextureRegistry {
+    handles: HashMap<String, TextureHandle>,
+    paths: Vec<String>,
+}
+
+impl TextureRegistry {
+    // Reports every texture whose file is missing at once, rather than one per run.
+    pub fn load(path: &str) -> Result<Self, String> {
+        let source = fs::read_to_string(path).map_err(|e| format!("Could not read texture manifest {}: {}", path, e))?;
+        let registry = Self::parse(&source).map_err(|e| format!("{}: {}", path, e))?;
+
+        let missing: Vec<String> = registry.handles.iter()
+            .filter(|(_, handle)| !Path::new(&registry.paths[handle.index()]).is_file())
+            .map(|(name, handle)| format!("{} ({})", name, registry.paths[handle.index()]))
+            .collect();
+        if !missing.is_empty() {
+            return Err(format!("{}: missing textures: {}", path, missing.join(", ")));
+        }
+        Ok(registry)
+    }
+
+    fn parse(source: &str) -> Result<Self, String> {
+        let line_pattern = Regex::new(r"^(\w+)\s+(\S+)$").unwrap();
+
+        let mut registry = TextureRegistry::default();
+        for (line_no, line) in source.lines().enumerate().map(|(i, l)| (i + 1, l.trim())) {
+            if line.is_empty() || line.starts_with("//") {
+                continue;
+            }
+            let cap = line_pattern.captures(line)
+                .ok_or_else(|| format!("line {}: could not parse \"{}\"", line_no, line))?;
+            if registry.handles.contains_key(&cap[1]) {
+                return Err(format!("line {}: \"{}\" is already in the manifest", line_no, &cap[1]));
+            }
+            registry.handles.insert(cap[1].to_string(), TextureHandle(registry.paths.len()));
+            registry.paths.push(cap[2].to_string());
+        }
+        Ok(registry)
+    }
+
+    pub fn get(&self, name: &str) -> Option<TextureHandle> {
+        self.handles.get(name).copied()
+    }
+
+    pub fn handle(&self, name: &str) -> Result<TextureHandle, String> {
+        self.get(name).ok_or_else(|| format!("There is no texture named \"{}\" in the manifest", name))
+    }
+
+    // The image files, in handle order.
+    pub fn paths(&self) -> &[String] {
+        &self.paths
+    }
+}
@@ -0,0 +1,288 @@
+use crate::level::{KINDS, Placement, kind_properties};
+use crate::textures::TextureRegistry;
+use crate::tilemap::{LayerKind, TileLayer, TileMap, Tileset};
+use std::{collections::{BTreeMap, HashSet}, fs, path::Path};
+use serde::Deserialize;
+use serde_json::Value;
+use sdl2::rect::Point;
+
+// Loads maps saved by the Tiled editor (https://www.mapeditor.org) as JSON, .tmj or .json, with the
+// tile layer format set to CSV.
+//
+// Tile layers become layers of the TileMap. Their kind is taken from a `kind` property, then from the
+// layer name, and otherwise the first is ground and the rest decoration. Tiles with a `solid` property
+// set to true, or with the class `solid`, block movement.
+//
+// Tilesets may be embedded or external (.tsj). Their image is looked up in the texture manifest by
+// file name without the extension, unless the tileset has a `spritesheet` property.
+//
+// Objects are read as level placements. Their class (type in older versions of Tiled) is the kind and
+// their custom properties and name the properties, see KINDS in src/level.rs.
+
+// Tiled stores flipping in the top bits of tile ids, which we don't support.
+const FLIP_FLAGS: u32 = 0xE000_0000;
+
+#[derive(Deserialize)]
+struct TiledMap {
+    width: u32,
+    height: u32,
+    tilewidth: u32,
+    tileheight: u32,
+    #[serde(default)]
+    infinite: bool,
+    layers: Vec<TiledLayer>,
+    tilesets: Vec<TiledTileset>,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type")]
+enum TiledLayer {
+    #[serde(rename = "tilelayer")]
+    Tiles {
+        name: String,
+        data: Value,
+        #[serde(default)]
+        properties: Vec<Property>,
+    },
+    #[serde(rename = "objectgroup")]
+    Objects {
+        name: String,
+        objects: Vec<TiledObject>,
+    },
+    #[serde(rename = "group")]
+    Group {
+        layers: Vec<TiledLayer>,
+    },
+    #[serde(rename = "imagelayer")]
+    Image,
+}
+
+#[derive(Deserialize)]
+struct TiledTileset {
+    #[serde(default)]
+    firstgid: u32,
+    source: Option<String>,
+    #[serde(default)]
+    name: String,
+    image: Option<String>,
+    columns: Option<u32>,
+    tilecount: Option<u32>,
+    imageheight: Option<u32>,
+    tileheight: Option<u32>,
+    #[serde(default)]
+    tiles: Vec<TiledTile>,
+    #[serde(default)]
+    properties: Vec<Property>,
+}
+
+#[derive(Deserialize)]
+struct TiledTile {
+    id: u32,
+    #[serde(rename = "type", alias = "class", default)]
+    class: String,
+    #[serde(default)]
+    properties: Vec<Property>,
+}
+
+#[derive(Deserialize)]
+struct TiledObject {
+    id: u32,
+    #[serde(default)]
+    name: String,
+    #[serde(rename = "type", default)]
+    kind: String,
+    #[serde(default)]
+    class: String,
+    x: f64,
+    y: f64,
+    #[serde(default)]
+    width: f64,
+    #[serde(default)]
+    height: f64,
+    gid: Option<u32>,
+    #[serde(default)]
+    properties: Vec<Property>,
+}
+
+#[derive(Deserialize)]
+struct Property {
+    name: String,
+    value: Value,
+}
+
+fn property<'a>(properties: &'a [Property], name: &str) -> Option<&'a Value> {
+    properties.iter().find(|p| p.name == name).map(|p| &p.value)
+}
+
+fn read_json<T: for<'de> Deserialize<'de>>(path: &Path) -> Result<T, String> {
+    let source = fs::read_to_string(path).map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+    serde_json::from_str(&source).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+// Returns the map's tiles and what its objects place.
+pub fn load(path: &str, textures: &TextureRegistry) -> Result<(TileMap, Vec<Placement>), String> {
+    let path = Path::new(path);
+    let tiled: TiledMap = read_json(path)?;
+    let directory = path.parent().unwrap_or_else(|| Path::new(""));
+
+    let map = build_tilemap(&tiled, directory, textures).map_err(|e| format!("{}: {}", path.display(), e))?;
+
+    let mut objects = Vec::new();
+    collect_objects(&tiled.layers, &mut objects);
+    let placements = objects.into_iter()
+        .map(|(layer, object)| placement(map.origin, object).map_err(|e| {
+            format!("{}: object {} \"{}\" in layer \"{}\": {}", path.display(), object.id, object.name, layer, e)
+        }))
+        .collect::<Result<Vec<Placement>, String>>()?;
+    Ok((map, placements))
+}
+
+fn build_tilemap(tiled: &TiledMap, directory: &Path, textures: &TextureRegistry) -> Result<TileMap, String> {
+    if tiled.infinite {
+        return Err("infinite maps are not supported".to_string());
+    }
+    if tiled.tilewidth == 0 || tiled.tileheight == 0 {
+        return Err(format!("tiles can't be {}x{}", tiled.tilewidth, tiled.tileheight));
+    }
+    let mut map = TileMap::default();
+    map.width = tiled.width;
+    map.height = tiled.height;
+    map.tile_width = tiled.tilewidth;
+    map.tile_height = tiled.tileheight;
+    map.origin = map.centered_origin();
+
+    for reference in &tiled.tilesets {
+        let external;
+        let tileset = match &reference.source {
+            Some(source) => {
+                external = read_json::<TiledTileset>(&directory.join(source))?;
+                &external
+            },
+            None => reference,
+        };
+        map.tilesets.push(build_tileset(tileset, reference.firstgid, textures)?);
+    }
+
+    let mut tile_layers = Vec::new();
+    collect_tile_layers(&tiled.layers, &mut tile_layers);
+    for (i, (name, data, properties)) in tile_layers.into_iter().enumerate() {
+        let kind = match property(properties, "kind").and_then(Value::as_str) {
+            Some(kind) => LayerKind::from_token(kind)
+                .ok_or_else(|| format!("layer \"{}\" has unknown kind \"{}\"", name, kind))?,
+            None => LayerKind::from_token(&name.to_lowercase())
+                .unwrap_or(if i == 0 { LayerKind::Ground } else { LayerKind::Decoration }),
+        };
+        let tiles = data.as_array()
+            .ok_or_else(|| format!("layer \"{}\" is compressed, save the map with the CSV layer format", name))?
+            .iter()
+            .map(|id| id.as_u64().map(|id| id as u32 & !FLIP_FLAGS))
+            .collect::<Option<Vec<u32>>>()
+            .ok_or_else(|| format!("layer \"{}\" has a tile that isn't a number", name))?;
+        if tiles.len() as u32 != map.width * map.height {
+            return Err(format!("layer \"{}\" doesn't have {}x{} tiles", name, map.width, map.height));
+        }
+        if let Some(id) = tiles.iter().find(|&&id| id != 0 && map.tileset(id).is_none()) {
+            return Err(format!("layer \"{}\" uses tile {}, which is in no tileset", name, id));
+        }
+        map.layers.push(TileLayer { name: name.clone(), kind, tiles });
+    }
+
+    map.update_collision();
+    Ok(map)
+}
+
+fn build_tileset(tileset: &TiledTileset, first_id: u32, textures: &TextureRegistry) -> Result<Tileset, String> {
+    let spritesheet_name = match property(&tileset.properties, "spritesheet").and_then(Value::as_str) {
+        Some(name) => name.to_string(),
+        None => {
+            let image = tileset.image.as_ref()
+                .ok_or_else(|| format!("tileset \"{}\" is a collection of images, which is not supported", tileset.name))?;
+            Path::new(image).file_stem().map(|stem| stem.to_string_lossy().to_lowercase()).unwrap_or_default()
+        },
+    };
+    let spritesheet = textures.get(&spritesheet_name)
+        .ok_or_else(|| format!("tileset \"{}\" needs the texture \"{}\", which isn't loaded", tileset.name, spritesheet_name))?;
+
+    let solid: HashSet<u32> = tileset.tiles.iter()
+        .filter(|tile| {
+            tile.class == "solid" || property(&tile.properties, "solid").and_then(Value::as_bool).unwrap_or(false)
+        })
+        .map(|tile| tile.id)
+        .collect();
+
+    let columns = tileset.columns.filter(|&c| c > 0)
+        .ok_or_else(|| format!("tileset \"{}\" has no columns", tileset.name))?;
+    // Older exports may leave out the count, which the image has room for.
+    let rows = match (tileset.imageheight, tileset.tileheight) {
+        (Some(height), Some(tile_height)) if tile_height > 0 => Some(height / tile_height),
+        _ => None,
+    };
+    let count = tileset.tilecount.or_else(|| rows.map(|rows| rows * columns))
+        .ok_or_else(|| format!("tileset \"{}\" has no tilecount", tileset.name))?;
+
+    Ok(Tileset {
+        spritesheet,
+        first_id,
+        columns,
+        count,
+        solid,
+    })
+}
+
+// Groups are flattened, keeping the order the layers are drawn in.
+fn collect_tile_layers<'a>(layers: &'a [TiledLayer], into: &mut Vec<(&'a String, &'a Value, &'a [Property])>) {
+    for layer in layers {
+        match layer {
+            TiledLayer::Tiles { name, data, properties } => into.push((name, data, properties)),
+            TiledLayer::Group { layers } => collect_tile_layers(layers, into),
+            _ => {},
+        }
+    }
+}
+
+fn collect_objects<'a>(layers: &'a [TiledLayer], into: &mut Vec<(&'a String, &'a TiledObject)>) {
+    for layer in layers {
+        match layer {
+            TiledLayer::Objects { name, objects } => into.extend(objects.iter().map(|object| (name, object))),
+            TiledLayer::Group { layers } => collect_objects(layers, into),
+            _ => {},
+        }
+    }
+}
+
+// Objects are placed by their top left corner in Tiled, or bottom left for tile objects, while positions
+// in the game are the middle of the entity.
+fn placement(origin: Point, object: &TiledObject) -> Result<Placement, String> {
+    let top = if object.gid.is_some() { object.y - object.height } else { object.y };
+    let position = Point::new(
+        origin.x() + (object.x + object.width / 2.0) as i32,
+        origin.y() + (top + object.height / 2.0) as i32,
+    );
+
+    let kind = if object.kind.is_empty() { &object.class } else { &object.kind };
+    if kind.is_empty() {
+        return Err("has no class".to_string());
+    }
+    if kind_properties(kind).is_none() {
+        let known: Vec<&str> = KINDS.iter().map(|(k, _)| *k).collect();
+        return Err(format!("unknown class \"{}\", expected one of {}", kind, known.join(", ")));
+    }
+
+    let mut properties: BTreeMap<String, String> = object.properties.iter()
+        .map(|p| {
+            let value = match &p.value {
+                Value::String(value) => value.clone(),
+                value => value.to_string(),
+            };
+            (p.name.clone(), value)
+        })
+        .collect();
+    if !object.name.is_empty() {
+        properties.insert("name".to_string(), object.name.clone());
+    }
+    Ok(Placement {
+        kind: kind.clone(),
+        position,
+        properties,
+    })
+}
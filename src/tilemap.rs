@@ -0,0 +1,267 @@
+use crate::components::Sprite;
+use crate::textures::{TextureHandle, TextureRegistry};
+use std::{collections::HashSet, fs};
+use regex::Regex;
+use sdl2::rect::{Point, Rect};
+
+// Tile map file format:
+//
+// tilesize 32 32                   Width and height of every tile, in pixels.
+// tileset terrain 4 8 solid 2 3    Spritesheet, number of columns in it, number of tiles, and optionally
+//                                  which of its tiles block movement. Tiles are counted from 0 within
+//                                  the sheet, left to right and top to bottom.
+// origin -400 -300                 Optional. World position of the top left corner. Without it the
+//                                  map is centered on the world origin.
+// [ground]                         Starts a layer: ground, decoration or overhead, optionally followed
+//                                  by a name. Ground and decoration are drawn below entities, overhead
+//                                  above them.
+// 1 1 1 2 2 .                      One row of tiles. Tilesets are numbered one after the other from 1,
+//                                  so with an 8 tile set first its tiles are 1 to 8 and the next set
+//                                  starts at 9. `.` or 0 leaves the spot empty.
+//
+// All layers must be the same size. Solid tiles on ground and decoration layers block movement, overhead
+// ones never do, so the player can walk under roofs and tree tops.
+
+#[derive(Debug, Clone)]
+pub struct Tileset {
+    pub spritesheet: TextureHandle,
+    pub first_id: u32,
+    pub columns: u32,
+    pub count: u32,
+    pub solid: HashSet<u32>,
+}
+
+impl Tileset {
+    fn contains(&self, id: u32) -> bool {
+        id >= self.first_id && id < self.first_id + self.count
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LayerKind {
+    Ground,
+    Decoration,
+    Overhead,
+}
+
+impl LayerKind {
+    pub fn from_token(token: &str) -> Option<Self> {
+        match token {
+            "ground" => Some(LayerKind::Ground),
+            "decoration" => Some(LayerKind::Decoration),
+            "overhead" => Some(LayerKind::Overhead),
+            _ => None,
+        }
+    }
+}
+
+// Tile ids count across all tilesets, 0 meaning no tile.
+#[derive(Debug, Clone)]
+pub struct TileLayer {
+    #[allow(dead_code)]
+    pub name: String,
+    pub kind: LayerKind,
+    pub tiles: Vec<u32>,
+}
+
+#[derive(Debug)]
+pub struct TileMap {
+    pub width: u32,
+    pub height: u32,
+    pub tile_width: u32,
+    pub tile_height: u32,
+    pub origin: Point,
+    pub tilesets: Vec<Tileset>,
+    pub layers: Vec<TileLayer>,
+    solid: Vec<bool>,
+}
+
+impl Default for TileMap {
+    fn default() -> Self {
+        TileMap {
+            width: 0,
+            height: 0,
+            tile_width: 1,
+            tile_height: 1,
+            origin: Point::new(0, 0),
+            tilesets: Vec::new(),
+            layers: Vec::new(),
+            solid: Vec::new(),
+        }
+    }
+}
+
+impl TileMap {
+    pub fn load(path: &str, textures: &TextureRegistry) -> Result<Self, String> {
+        let source = fs::read_to_string(path).map_err(|e| format!("Could not read map file {}: {}", path, e))?;
+        Self::parse(&source, textures).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    fn parse(source: &str, textures: &TextureRegistry) -> Result<Self, String> {
+        let tilesize_pattern = Regex::new(r"^tilesize (\d+) (\d+)$").unwrap();
+        let tileset_pattern = Regex::new(r"^tileset (\w+) (\d+) (\d+)(?: solid((?: \d+)+))?$").unwrap();
+        let origin_pattern = Regex::new(r"^origin (-?\d+) (-?\d+)$").unwrap();
+        let layer_pattern = Regex::new(r"^\[(\w+)(?: (\w+))?\]$").unwrap();
+
+        let mut map = TileMap::default();
+        let mut origin = None;
+        let mut rows: Vec<Vec<u32>> = Vec::new();
+        let mut current: Option<(String, LayerKind)> = None;
+        let mut layers: Vec<(String, LayerKind, Vec<Vec<u32>>)> = Vec::new();
+
+        for (line_no, line) in source.lines().enumerate().map(|(i, l)| (i + 1, l.trim())) {
+            if line.is_empty() || line.starts_with("//") {
+                continue;
+            }
+            if let Some(cap) = layer_pattern.captures(line) {
+                let kind = LayerKind::from_token(&cap[1])
+                    .ok_or_else(|| format!("line {}: unknown layer kind \"{}\"", line_no, &cap[1]))?;
+                if let Some((name, kind)) = current.take() {
+                    layers.push((name, kind, std::mem::take(&mut rows)));
+                }
+                let name = cap.get(2).map_or(&cap[1], |m| m.as_str()).to_string();
+                current = Some((name, kind));
+            } else if current.is_some() {
+                let row = line.split_whitespace()
+                    .map(|token| match token {
+                        "." => Ok(0),
+                        _ => token.parse::<u32>(),
+                    })
+                    .collect::<Result<Vec<u32>, _>>()
+                    .map_err(|_| format!("line {}: could not parse tiles \"{}\"", line_no, line))?;
+                rows.push(row);
+            } else if let Some(cap) = tilesize_pattern.captures(line) {
+                map.tile_width = cap[1].parse().map_err(|e| format!("line {}: {}", line_no, e))?;
+                map.tile_height = cap[2].parse().map_err(|e| format!("line {}: {}", line_no, e))?;
+                if map.tile_width == 0 || map.tile_height == 0 {
+                    return Err(format!("line {}: tiles can't be {}x{}", line_no, map.tile_width, map.tile_height));
+                }
+            } else if let Some(cap) = tileset_pattern.captures(line) {
+                let spritesheet = textures.get(&cap[1])
+                    .ok_or_else(|| format!("line {}: unknown spritesheet \"{}\"", line_no, &cap[1]))?;
+                let number = |i: usize| cap[i].parse::<u32>().map_err(|e| format!("line {}: {}", line_no, e));
+                let tileset = Tileset {
+                    spritesheet,
+                    first_id: map.tilesets.last().map_or(1, |t| t.first_id + t.count),
+                    columns: number(2)?,
+                    count: number(3)?,
+                    solid: cap.get(4)
+                        .map(|m| m.as_str().split_whitespace().filter_map(|t| t.parse().ok()).collect())
+                        .unwrap_or_default(),
+                };
+                if tileset.columns == 0 {
+                    return Err(format!("line {}: a tileset needs at least one column", line_no));
+                }
+                map.tilesets.push(tileset);
+            } else if let Some(cap) = origin_pattern.captures(line) {
+                let number = |i: usize| cap[i].parse::<i32>().map_err(|e| format!("line {}: {}", line_no, e));
+                origin = Some(Point::new(number(1)?, number(2)?));
+            } else {
+                return Err(format!("line {}: could not parse \"{}\"", line_no, line));
+            }
+        }
+        if let Some((name, kind)) = current.take() {
+            layers.push((name, kind, rows));
+        }
+
+        let (_, _, first) = layers.first().ok_or("the map has no layers")?;
+        map.height = first.len() as u32;
+        map.width = first.first().map_or(0, |row| row.len() as u32);
+        for (name, kind, rows) in layers {
+            if rows.len() as u32 != map.height || rows.iter().any(|row| row.len() as u32 != map.width) {
+                return Err(format!("layer [{}] is not {}x{} tiles like the first one", name, map.width, map.height));
+            }
+            let tiles: Vec<u32> = rows.into_iter().flatten().collect();
+            if let Some(id) = tiles.iter().find(|&&id| id != 0 && map.tileset(id).is_none()) {
+                return Err(format!("layer [{}] uses tile {}, which is in no tileset", name, id));
+            }
+            map.layers.push(TileLayer { name, kind, tiles });
+        }
+
+        map.origin = origin.unwrap_or_else(|| map.centered_origin());
+        map.update_collision();
+        Ok(map)
+    }
+
+    // The origin that puts the middle of the map on the middle of the world.
+    pub fn centered_origin(&self) -> Point {
+        Point::new(
+            -((self.width * self.tile_width) as i32) / 2,
+            -((self.height * self.tile_height) as i32) / 2,
+        )
+    }
+
+    pub fn tileset(&self, id: u32) -> Option<&Tileset> {
+        self.tilesets.iter().find(|tileset| tileset.contains(id))
+    }
+
+    pub fn tile_sprite(&self, id: u32) -> Option<Sprite> {
+        let tileset = self.tileset(id)?;
+        let index = id - tileset.first_id;
+        Some(Sprite {
+            spritesheet: tileset.spritesheet,
+            region: Rect::new(
+                ((index % tileset.columns) * self.tile_width) as i32,
+                ((index / tileset.columns) * self.tile_height) as i32,
+                self.tile_width,
+                self.tile_height,
+            ),
+        })
+    }
+
+    // Where a tile is in the world.
+    pub fn tile_rect(&self, column: u32, row: u32) -> Rect {
+        Rect::new(
+            self.origin.x() + (column * self.tile_width) as i32,
+            self.origin.y() + (row * self.tile_height) as i32,
+            self.tile_width,
+            self.tile_height,
+        )
+    }
+
+    // Works out which tiles block movement. Call after changing the layers or tilesets.
+    pub fn update_collision(&mut self) {
+        let mut solid = vec![false; (self.width * self.height) as usize];
+        for layer in self.layers.iter().filter(|layer| layer.kind != LayerKind::Overhead) {
+            for (i, &id) in layer.tiles.iter().enumerate() {
+                if let Some(tileset) = self.tileset(id) {
+                    solid[i] |= tileset.solid.contains(&(id - tileset.first_id));
+                }
+            }
+        }
+        self.solid = solid;
+    }
+
+    pub fn is_solid(&self, column: u32, row: u32) -> bool {
+        column < self.width && row < self.height && self.solid[(row * self.width + column) as usize]
+    }
+
+    // Does a rectangle in the world overlap any solid tile?
+    pub fn blocks(&self, rect: Rect) -> bool {
+        if self.width == 0 || self.height == 0 {
+            return false;
+        }
+        let tw = self.tile_width as i32;
+        let th = self.tile_height as i32;
+        let first_column = ((rect.left() - self.origin.x()).div_euclid(tw)).max(0);
+        let last_column = ((rect.right() - 1 - self.origin.x()).div_euclid(tw)).min(self.width as i32 - 1);
+        let first_row = ((rect.top() - self.origin.y()).div_euclid(th)).max(0);
+        let last_row = ((rect.bottom() - 1 - self.origin.y()).div_euclid(th)).min(self.height as i32 - 1);
+
+        (first_row..=last_row).any(|row| {
+            (first_column..=last_column).any(|column| self.is_solid(column as u32, row as u32))
+        })
+    }
+
+    // Is the straight line between two points in the world clear of solid tiles? Checked every half tile.
+    pub fn sees(&self, from: Point, to: Point) -> bool {
+        let step = (self.tile_width.min(self.tile_height) / 2).max(1) as f64;
+        let (dx, dy) = ((to.x() - from.x()) as f64, (to.y() - from.y()) as f64);
+        let steps = (dx.hypot(dy) / step).ceil() as i32;
+        (0..=steps).all(|i| {
+            let t = if steps == 0 { 0.0 } else { i as f64 / steps as f64 };
+            let point = Point::new(from.x() + (dx * t) as i32, from.y() + (dy * t) as i32);
+            !self.blocks(Rect::new(point.x(), point.y(), 1, 1))
+        })
+    }
+}
@@ -0,0 +1,33 @@
+use specs::{System, ReadExpect, WriteExpect};
+use crate::components::*;
+use std::time::{Duration, Instant};
+
+pub struct Typewriter;
+
+impl<'a> System<'a> for Typewriter {
+
+    type SystemData = (
+        ReadExpect<'a, Gamestate>,
+        ReadExpect<'a, DialogueSettings>,
+        WriteExpect<'a, Dialogue_Helper>,
+    );
+
+    fn run(&mut self, (gamestate, settings, mut layout): Self::SystemData) {
+        // The renderer lays out new lines, so there is nothing to reveal until it has.
+        if *gamestate != Gamestate::Dialogue || layout.pages.is_empty() {
+            return;
+        }
+
+        let now = Instant::now();
+        let char_delay = Duration::from_secs_f64(1.0 / settings.characters_per_second.max(1) as f64);
+        let mut next_char_at = layout.next_char_at.unwrap_or(now);
+        let page_end = layout.page_end();
+
+        while layout.revealed < page_end && next_char_at <= now {
+            layout.revealed += 1;
+            let pause = layout.pause_after(layout.revealed);
+            next_char_at += char_delay + Duration::from_millis(pause as u64);
+        }
+        layout.next_char_at = Some(next_char_at);
+    }
+}
@@ -1,8 +1,22 @@
 use specs::{ReadStorage, join::Join, WriteStorage, System};
+use sdl2::rect::{Rect, Point};
 use crate::components::*;
 
 pub struct IZUpdater;
 
+// Centers `rect` just outside the collision box of something at `center`, on the side it faces.
+pub fn place_in_front(rect: &mut Rect, center: Point, col: &CollisionBox, direction: Direction) {
+    let vertical = (col.height as i32 + rect.height() as i32)/2;
+    let horizontal = (col.width as i32 + rect.width() as i32)/2;
+    let offset = match direction {
+        Direction::Up => Point::new(0, -vertical),
+        Direction::Down => Point::new(0, vertical),
+        Direction::Left => Point::new(-horizontal, 0),
+        Direction::Right => Point::new(horizontal, 0),
+    };
+    rect.center_on(center + offset);
+}
+
 impl<'a> System<'a> for IZUpdater {
     
     type SystemData = (
@@ -16,31 +30,20 @@ impl<'a> System<'a> for IZUpdater {
 
         for (pos, intzone, theface, col) in (&position, &mut interactionzone, &facing, &collisionbox).join() {
 
-            let mut best_point;
-            
+            // The zone is long and narrow, lying across whichever way we face.
             match theface.direction {
                 Direction::Up | Direction::Down => {
                     if intzone.flipped {
                         intzone.flip();
                     }
-                    best_point = (col.height as i32 + intzone.rect.height() as i32)/2;
-                    if theface.direction == Direction::Up {
-                        best_point *= -1;
-                    }
-                    intzone.rect.center_on(pos.0.offset(0, best_point));
                 }
                 Direction::Left | Direction::Right => {
                     if !intzone.flipped {
                         intzone.flip();
                     }
-                    best_point = (col.width as i32 + intzone.rect.width() as i32)/2;
-                    if theface.direction == Direction::Left {
-                        best_point *= -1;
-                    }
-                    intzone.rect.center_on(pos.0.offset(best_point, 0));
                 }
             }
-            
+            place_in_front(&mut intzone.rect, pos.0, col, theface.direction);
         }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,196 @@
+use std::{collections::HashMap, fmt};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+// Named game variables that persist for the whole session, e.g. `met_reaper = true` or `chests_opened = 2`.
+// Dialogue files test them with conditions and change them with effects.
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Variable {
+    Bool(bool),
+    Int(i64),
+    Str(String),
+}
+
+impl Variable {
+    fn parse(value: &str) -> Result<Self, String> {
+        let value = value.trim();
+        match value {
+            "true" => Ok(Variable::Bool(true)),
+            "false" => Ok(Variable::Bool(false)),
+            _ if value.len() >= 2 && value.starts_with('"') && value.ends_with('"') => {
+                Ok(Variable::Str(value[1..value.len() - 1].to_string()))
+            },
+            _ => value.parse::<i64>()
+                .map(Variable::Int)
+                .map_err(|_| format!("\"{}\" is not a bool, integer or quoted string", value)),
+        }
+    }
+
+    // What an unset variable is taken to be when compared against `self`.
+    fn unset_like(&self) -> Self {
+        match self {
+            Variable::Bool(_) => Variable::Bool(false),
+            Variable::Int(_) => Variable::Int(0),
+            Variable::Str(_) => Variable::Str(String::new()),
+        }
+    }
+
+    fn is_truthy(&self) -> bool {
+        match self {
+            Variable::Bool(b) => *b,
+            Variable::Int(i) => *i != 0,
+            Variable::Str(s) => !s.is_empty(),
+        }
+    }
+}
+
+impl fmt::Display for Variable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Variable::Bool(b) => write!(f, "{}", b),
+            Variable::Int(i) => write!(f, "{}", i),
+            Variable::Str(s) => write!(f, "\"{}\"", s),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct WorldVariables {
+    values: HashMap<String, Variable>,
+}
+
+impl WorldVariables {
+    pub fn get(&self, name: &str) -> Option<&Variable> {
+        self.values.get(name)
+    }
+
+    pub fn set(&mut self, name: &str, value: Variable) {
+        self.values.insert(name.to_string(), value);
+    }
+
+    pub fn add(&mut self, name: &str, amount: i64) {
+        let current = match self.get(name) {
+            Some(Variable::Int(i)) => *i,
+            _ => 0,
+        };
+        self.set(name, Variable::Int(current + amount));
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Clause {
+    IsSet { name: String, negated: bool },
+    Compare { name: String, comparison: Comparison, value: Variable },
+}
+
+// One or more clauses joined by `&&`, e.g. `met_reaper && chests_opened >= 1`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Condition {
+    clauses: Vec<Clause>,
+}
+
+impl Condition {
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let compare_pattern = Regex::new(r"^(\w+) *(==|!=|>=|<=|>|<) *(.+)$").unwrap();
+        let flag_pattern = Regex::new(r"^(!?)(\w+)$").unwrap();
+
+        let mut clauses = Vec::new();
+        for clause in source.split("&&").map(str::trim) {
+            if let Some(cap) = compare_pattern.captures(clause) {
+                let comparison = match &cap[2] {
+                    "==" => Comparison::Equal,
+                    "!=" => Comparison::NotEqual,
+                    "<" => Comparison::Less,
+                    "<=" => Comparison::LessOrEqual,
+                    ">" => Comparison::Greater,
+                    _ => Comparison::GreaterOrEqual,
+                };
+                clauses.push(Clause::Compare {
+                    name: cap[1].to_string(),
+                    comparison,
+                    value: Variable::parse(&cap[3])?,
+                });
+            } else if let Some(cap) = flag_pattern.captures(clause) {
+                clauses.push(Clause::IsSet {
+                    name: cap[2].to_string(),
+                    negated: &cap[1] == "!",
+                });
+            } else {
+                return Err(format!("could not parse condition \"{}\"", clause));
+            }
+        }
+        Ok(Condition { clauses })
+    }
+
+    pub fn holds(&self, variables: &WorldVariables) -> bool {
+        self.clauses.iter().all(|clause| match clause {
+            Clause::IsSet { name, negated } => {
+                variables.get(name).is_some_and(Variable::is_truthy) != *negated
+            },
+            Clause::Compare { name, comparison, value } => {
+                let unset = value.unset_like();
+                let current = variables.get(name).unwrap_or(&unset);
+                compare(current, *comparison, value)
+            },
+        })
+    }
+}
+
+fn compare(left: &Variable, comparison: Comparison, right: &Variable) -> bool {
+    use Comparison::*;
+    let ordering = match (left, right) {
+        (Variable::Int(l), Variable::Int(r)) => l.cmp(r),
+        (Variable::Str(l), Variable::Str(r)) => l.cmp(r),
+        (Variable::Bool(l), Variable::Bool(r)) => l.cmp(r),
+        // Different types are never equal and can't be ordered.
+        _ => return comparison == NotEqual,
+    };
+    match comparison {
+        Equal => ordering.is_eq(),
+        NotEqual => ordering.is_ne(),
+        Less => ordering.is_lt(),
+        LessOrEqual => ordering.is_le(),
+        Greater => ordering.is_gt(),
+        GreaterOrEqual => ordering.is_ge(),
+    }
+}
+
+// A change to a variable, e.g. `met_reaper = true` or `times_talked += 1`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Effect {
+    Set(String, Variable),
+    Add(String, i64),
+}
+
+impl Effect {
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let effect_pattern = Regex::new(r"^(\w+) *(=|\+=|-=) *(.+)$").unwrap();
+        let cap = effect_pattern.captures(source.trim())
+            .ok_or_else(|| format!("could not parse effect \"{}\"", source))?;
+        let name = cap[1].to_string();
+        match (&cap[2], Variable::parse(&cap[3])?) {
+            ("=", value) => Ok(Effect::Set(name, value)),
+            ("+=", Variable::Int(amount)) => Ok(Effect::Add(name, amount)),
+            ("-=", Variable::Int(amount)) => Ok(Effect::Add(name, -amount)),
+            (_, value) => Err(format!("can only add or subtract integers, not {}", value)),
+        }
+    }
+
+    pub fn apply(&self, variables: &mut WorldVariables) {
+        match self {
+            Effect::Set(name, value) => variables.set(name, value.clone()),
+            Effect::Add(name, amount) => variables.add(name, *amount),
+        }
+    }
+}